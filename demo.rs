@@ -1,3 +1,5 @@
+extern crate collections;
+
 use std::os;
 use optparse::{Opt, OptionParser};
 
@@ -11,23 +13,22 @@ fn main() {
 	let long_opt = Opt::new("", "--long-opt", "An option with no short variant");
 	let int_arg = Opt::new("-i", "--int-arg [ARG]", "Option that takes an int arg");
 	let multi_value_arg = Opt::new("-m", "--multi-arg [ARGS]", "Option that can be repeated");
-	let version_opt = Opt::version_opt();
 
 	// specify the syntax, banner and options for the command
-	let opt_parser = OptionParser {
-		usage : "[<values to print>...]".to_owned(),
-		banner : "This is an example app for the optparser module. \
-		           The banner is a short summary which appears at the top of
-		           --help output".to_owned(),
-		opts : ~[&simple_opt, &opt_with_opt_arg, &long_opt, &int_arg, &opt_with_req_arg,
-		         &multi_value_arg, &version_opt],
-		tail_banner : Some("This is a tail banner that appears below the list of options".to_owned())
-	};
+	let mut opt_parser = OptionParser::new("[<values to print>...]",
+		"This is an example app for the optparser module.\n\n\
+		 The banner is a short summary which appears at the top of \
+		 --help output.  Paragraphs separated by blank lines are \
+		 wrapped separately.",
+		[&simple_opt, &opt_with_opt_arg, &long_opt, &int_arg, &opt_with_req_arg,
+		 &multi_value_arg]);
+	opt_parser.tail_banner = Some("This is a tail banner that appears below the list of options".to_owned());
+	opt_parser.enable_version("1.0");
 
 	let flags = opt_parser.parse(os::args());
 
 	match flags.status {
-		optparse::Help => return,
+		optparse::Help | optparse::Version => return,
 		optparse::Error => {
 			os::set_exit_status(1);
 			return
@@ -52,10 +53,6 @@ fn main() {
 		println!("An option with only the long opt form was used");
 	}
 
-	if opt_parser.is_set(&flags, &version_opt) {
-		println!("Version opt was used");
-	}
-
 	match opt_parser.value(&flags, &opt_with_opt_arg) {
 		Some(v) => println!("An option with an optional arg {} was used", v),
 		None => ()
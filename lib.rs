@@ -1,5 +1,14 @@
 #![crate_id = "optparse"]
 
-pub use optparse::{Opt, OptMatch, OptionParser, ParseStatus, ParseResult};
+extern crate collections;
+
+pub use optparse::{ArgCheck, ArgType, Messages, Opt, OptMatch, OptionParser, OptionState, ParseStatus, ParseResult, Token, UnknownPolicy, ValueError, ValuesIter, wrap};
+pub use optparse::{StrArg, IntArg, FloatArg, PathArg};
+pub use optparse::{PathExists, PathIsFile, PathIsDir};
+pub use optparse::{Absent, Present, PresentWith};
+pub use optparse::{Success, Help, Version, Error};
+pub use optparse::{OptionToken, PositionalToken};
+pub use optparse::{RejectUnknown, PassThroughUnknown, CollectUnknown};
 
 mod optparse;
+pub mod testing;
@@ -1,9 +1,16 @@
+use collections::HashMap;
+use std::default::Default;
+use std::fmt;
+use std::from_str::FromStr;
 use std::os;
-use std::io::stdio::print;
-use std::num::Bounded;
+use std::io::File;
+use std::io::stdio;
+use std::io::stdio::println;
+use std::slice;
 use std::strbuf::StrBuf;
 
 /// Represents a command-line flag
+#[deriving(Clone)]
 pub struct Opt {
 	/// Short version of argument, consisting of a '-' followed
 	/// by a single letter
@@ -12,14 +19,222 @@ pub struct Opt {
 	/// one or more letters
 	pub long: ~str,
 	/// A brief description of the option
-	pub description: ~str
+	pub description: ~str,
+	/// An example of the option's usage, displayed beneath
+	/// the description in --help output
+	pub example: Option<~str>,
+	/// If true, the option can be unset by a later '--no-<name>'
+	/// argument, eg. '--no-feature' for '--feature'
+	pub negatable: bool,
+	/// The accepted values for the option's argument.  If empty,
+	/// any value is accepted
+	pub choices: Vec<~str>,
+	/// The value returned by OptionParser::value() if the option
+	/// is not passed on the command-line
+	pub default: Option<~str>,
+	/// The type of the option's argument.  If set, the option
+	/// takes an argument even if 'long' does not name one
+	pub arg_type: Option<ArgType>,
+	/// A check applied to the option's argument when parsing.
+	/// This is not set by default, so that options naming files
+	/// which are yet to be created are accepted
+	pub check: Option<ArgCheck>,
+	/// The characters which the option's argument may contain, eg.
+	/// 'A-Za-z0-9_' for identifiers.  This is a list of characters
+	/// and ranges such as 'a-z', as in a regular expression character
+	/// class without the brackets.  A '-' at the start or end of the
+	/// list stands for itself.  If set, the argument must be non-empty
+	/// and consist only of these characters
+	pub pattern: Option<~str>,
+	/// The maximum number of times that the option may be
+	/// given.  If not set, the option may be repeated any
+	/// number of times
+	pub max_occurrences: Option<uint>,
+	/// If true, all of the arguments which follow the option are
+	/// taken as its values without any further option parsing,
+	/// eg. for '--exec ls -la /tmp'.  Those arguments are kept
+	/// exactly as given, and can be retrieved with
+	/// OptionParser::values()
+	pub consumes_rest: bool,
+	/// If true, leading and trailing whitespace is removed from
+	/// values given for the option on the command-line, before they
+	/// are checked.  Values read from a config file are always trimmed
+	pub trim: bool,
+	/// If true, the option must be given and OptionParser::parse()
+	/// reports an error if it is not
+	pub required: bool,
+	/// If true, the option is accepted by OptionParser::parse() but
+	/// left out of --help output and the other descriptions of the
+	/// parser's options, eg. for deprecated or debugging options
+	pub hidden: bool
+}
+
+/// The type of value expected by an option's argument.  The type
+/// determines the argument name shown in --help output if the
+/// option does not specify one and the values accepted by
+/// OptionParser::parse()
+#[deriving(Clone)]
+pub enum ArgType {
+	/// Any string, shown as 'VALUE'
+	StrArg,
+	/// An integer, shown as 'N'
+	IntArg,
+	/// A floating-point number, shown as 'NUM'
+	FloatArg,
+	/// A file path, shown as 'FILE'
+	PathArg
+}
+
+/// A check applied to the value of an option by
+/// OptionParser::parse(), for options whose values name files
+#[deriving(Clone)]
+pub enum ArgCheck {
+	/// The value must be the path of an existing file or directory
+	PathExists,
+	/// The value must be the path of an existing file
+	PathIsFile,
+	/// The value must be the path of an existing directory
+	PathIsDir
+}
+
+/// How OptionParser::parse() handles an option which is not
+/// recognized
+pub enum UnknownPolicy {
+	/// The option is reported as an error
+	RejectUnknown,
+	/// The option is added to the non-option arguments in
+	/// ParseResult::args, eg. for wrappers which forward it
+	/// to another program
+	PassThroughUnknown,
+	/// The option is added to ParseResult::unknown
+	CollectUnknown
+}
+
+/// Templates for the messages and built-in text produced by an
+/// OptionParser, which can be replaced eg. to translate them.
+///
+/// Placeholders of the form '{name}' in a template are replaced
+/// with the values listed for each message.  The English defaults
+/// are returned by Default::default()
+pub struct Messages {
+	/// An option is not recognized: {option}
+	pub unknown_option : ~str,
+	/// An option is not recognized but is similar to a known
+	/// one: {option}, {suggestion}
+	pub unknown_option_suggestion : ~str,
+	/// An option is not recognized and several known options are
+	/// equally similar to it: {option}, {suggestions}
+	pub unknown_option_suggestions : ~str,
+	/// An abbreviated option matches several options: {option},
+	/// {candidates}
+	pub ambiguous_option : ~str,
+	/// A non-option argument is not a known subcommand: {command}
+	pub unknown_subcommand : ~str,
+	/// An abbreviated subcommand matches several subcommands:
+	/// {command}, {candidates}
+	pub ambiguous_subcommand : ~str,
+	/// An option follows a non-option argument when options must
+	/// come first: {option}, {arg}
+	pub option_after_positional : ~str,
+	/// A value is attached to an option which does not take an
+	/// argument, eg. '--verbose=1': {option}
+	pub unexpected_argument : ~str,
+	/// A required option is not given: {option}
+	pub missing_option : ~str,
+	/// None of a group of options, one of which is required,
+	/// is given: {options}
+	pub missing_one_of : ~str,
+	/// More than one of a group of options, exactly one of which
+	/// is required, is given: {options}, {given}
+	pub too_many_of_one_of : ~str,
+	/// An option is the last argument and is missing its required
+	/// argument: {option}
+	pub missing_argument : ~str,
+	/// An option requiring an argument is followed by another
+	/// option instead: {option}, {next}
+	pub argument_is_option : ~str,
+	/// A non-option argument is spelled like an option name
+	/// without its leading dashes: {arg}, {option}
+	pub missing_dashes : ~str,
+	/// Fewer non-option arguments are given than the variadic
+	/// argument requires: {name}, {min}, {count}
+	pub too_few_arguments : ~str,
+	/// An option is given more than once when it may be given at
+	/// most once: {option}, {count}
+	pub too_many_occurrences_once : ~str,
+	/// An option is given more times than allowed: {option},
+	/// {max}, {count}
+	pub too_many_occurrences : ~str,
+	/// A value is not one of an option's choices: {option},
+	/// {value}, {choices}
+	pub invalid_choice : ~str,
+	/// A value is not one of an option's choices but is similar
+	/// to one: {option}, {value}, {suggestion}
+	pub invalid_choice_suggestion : ~str,
+	/// A value cannot be converted to the type requested with
+	/// OptionParser::value_as(): {option}, {value}
+	pub invalid_value : ~str,
+	/// A value contains characters which are not allowed by the
+	/// option's pattern: {option}, {value}
+	pub invalid_characters : ~str,
+	/// A value is not an integer: {option}, {value}
+	pub invalid_integer : ~str,
+	/// A value is not a number: {option}, {value}
+	pub invalid_number : ~str,
+	/// A value is not a size: {option}, {value}
+	pub invalid_size : ~str,
+	/// A value is not a duration: {option}, {value}
+	pub invalid_duration : ~str,
+	/// A list value has unbalanced quotes: {option}, {value}
+	pub unbalanced_quotes : ~str,
+	/// A path does not exist: {option}, {value}
+	pub path_not_found : ~str,
+	/// A path is not a file: {option}, {value}
+	pub path_not_file : ~str,
+	/// A path is not a directory: {option}, {value}
+	pub path_not_dir : ~str,
+	/// The usage line of --help output: {program}, {usage}
+	pub usage : ~str,
+	/// An option's example in --help output: {example}
+	pub example : ~str,
+	/// An option's description in --help output when defaults
+	/// are shown: {description}, {default}
+	pub description_with_default : ~str,
+	/// The heading of the required options in --help output,
+	/// if they are grouped
+	pub required_options : ~str,
+	/// The heading of the optional options in --help output,
+	/// if they are grouped
+	pub optional_options : ~str,
+	/// A one-line summary of the options: {options}
+	pub options_summary : ~str,
+	/// The description of the automatically added --help option
+	pub help_description : ~str,
+	/// The description of the automatically added --version option
+	pub version_description : ~str,
+	/// A config file could not be read: {path}, {error}
+	pub config_unreadable : ~str,
+	/// A config file line is not of the form 'name = value':
+	/// {location}, {line}
+	pub config_syntax_error : ~str,
+	/// A config file names an unknown option: {location}, {option}
+	pub config_unknown_option : ~str,
+	/// A config file gives a value other than 'true' or 'false'
+	/// for an option which takes no argument: {location},
+	/// {option}, {value}
+	pub config_invalid_flag : ~str
 }
 
 /// Parser for processing command-line arguments and displaying
 /// usage information
 pub struct OptionParser<'a> {
 	/// A one line usage summary to be displayed by -h.
-	/// The output format is '<program name> <usage>'
+	/// The output format is '<program name> <usage>'.
+	///
+	/// The usage and the banners may contain '{program}' and
+	/// '{version}' placeholders, which are replaced with the
+	/// program name and version.  Other text in braces is left
+	/// as-is
 	pub usage: ~str,
 	/// A short summary of what the command does,
 	/// displayed underneath the 'usage' string by -h
@@ -29,67 +244,761 @@ pub struct OptionParser<'a> {
 	pub opts: ~[&'a Opt],
 	/// A banner that is displayed below the list
 	/// of options
-	pub tail_banner : Option<~str>
+	pub tail_banner : Option<~str>,
+	/// The width to which --help output is wrapped.
+	/// The banners are wrapped to this width.
+	pub help_width : uint,
+	/// The column at which option descriptions start
+	/// in --help output
+	pub description_col : uint,
+	/// If true, option descriptions in --help output start on
+	/// the line after the option flags instead of at
+	/// description_col.  This layout is also used if help_width
+	/// is less than 40, for narrow terminals
+	pub compact_help : bool,
+	/// If true, a '-h/--help' option is automatically added
+	/// which prints usage information when used
+	pub auto_help : bool,
+	/// The option which prints usage information if auto_help
+	/// is set.  If not set, '-h/--help' is used.  See set_help_opt()
+	pub help_opt : Option<Opt>,
+	/// If true, the default values of options are shown
+	/// in --help output
+	pub show_defaults : bool,
+	/// A closing section displayed after the tail banner,
+	/// eg. for examples or a 'Report bugs to ...' line.
+	/// Unlike the banners, line breaks in the epilog are
+	/// preserved and each line is wrapped separately
+	pub epilog : Option<~str>,
+	/// The program name displayed in --help output.  If not set,
+	/// the name is taken from os::args()
+	pub program_name : Option<~str>,
+	/// If true, the option list in --help output is split into
+	/// required and optional options, under separate headings
+	pub group_by_required : bool,
+	/// If true, the usage line of --help output is printed
+	/// after the error message when parse() fails
+	pub print_usage_on_error : bool,
+	/// If true, the 'K', 'M' and 'G' suffixes accepted by
+	/// size_value() are powers of 1000 rather than 1024
+	pub decimal_sizes : bool,
+	/// If true, parse() does not print usage information or
+	/// errors.  These can instead be retrieved from the
+	/// returned ParseResult using help_text() and errors
+	pub quiet : bool,
+	/// Default option values set with set_default(), keyed
+	/// by the long names of the options
+	pub defaults : HashMap<~str, ~str>,
+	/// The version of the program
+	pub version : Option<~str>,
+	/// If true and a version is set, --help output starts
+	/// with a line giving the program name and version
+	pub show_version_in_help : bool,
+	/// If true, a '-v/--version' option is automatically added
+	/// which prints the program name and version when used.
+	/// This is set by enable_version()
+	pub auto_version : bool,
+	/// The name of the trailing variadic argument which collects
+	/// the non-option arguments, if any.  See set_variadic()
+	pub variadic_name : Option<~str>,
+	/// The minimum number of arguments which the variadic
+	/// argument must be given
+	pub variadic_min : uint,
+	/// The names of the subcommands which the first non-option
+	/// argument may give, eg. 'status' for 'git status'.  If not
+	/// empty, parse() reports an error if the first non-option
+	/// argument is not one of them
+	pub subcommands : Vec<~str>,
+	/// If true, subcommands may be abbreviated to any unambiguous
+	/// prefix of their name, eg. 'stat' for 'status'.  The
+	/// abbreviation is replaced with the full name in
+	/// ParseResult::args.  Names given in full always match exactly
+	pub abbreviate_subcommands : bool,
+	/// Groups of options, given by their long names, exactly one
+	/// of which must be given.  See add_required_one_of()
+	pub required_one_of : Vec<Vec<~str>>,
+	/// The characters which may separate a long option from an
+	/// attached value, eg. ':' for '--option:value'.  Defaults
+	/// to '='
+	pub value_separators : Vec<char>,
+	/// If false, arguments starting with a single dash (eg. '-abc')
+	/// are treated as non-option arguments rather than groups of
+	/// short options, so that only long options are recognized.
+	/// Defaults to true
+	pub short_options_enabled : bool,
+	/// If true, options must be given before any non-option
+	/// arguments and parse() reports an error for options which
+	/// follow one, other than the help and version options
+	pub options_before_positionals : bool,
+	/// The argument which ends option parsing, '--' by default.
+	/// The arguments which follow it are treated as non-option
+	/// arguments, eg. a tool which passes '--' on to another program
+	/// might use '++' instead
+	pub terminator : ~str,
+	/// The long name of the option which takes the arguments after
+	/// a '--' terminator as its values, instead of them being added
+	/// to ParseResult::args.  See set_trailing_target()
+	pub trailing_target : Option<~str>,
+	/// If true, option parsing stops at the first unknown option
+	/// and it and all of the arguments which follow it are treated
+	/// as non-option arguments, eg. for commands which run another
+	/// command with its own options.  This takes precedence over
+	/// unknown_short and unknown_long
+	pub stop_at_unknown : bool,
+	/// How unknown short options (eg. '-x') are handled.
+	/// Defaults to RejectUnknown
+	pub unknown_short : UnknownPolicy,
+	/// How unknown long options (eg. '--child-flag') are handled.
+	/// Defaults to RejectUnknown
+	pub unknown_long : UnknownPolicy,
+	/// The templates for error messages and other built-in text
+	pub messages : Messages,
+	// the handlers invoked by run() for each option, keyed by
+	// its long name
+	handlers : Vec<(~str, |Option<&str>|: 'a)>
 }
 
 /// Holds the option name and value of a matched command-line
 /// option
+#[deriving(Clone)]
 pub struct OptMatch {
 	opt_name : ~str,
-	val : ~str
+	// None for an option given without a value, which is
+	// distinct from an explicitly empty value, eg. '--name='
+	val : Option<~str>,
+	negated : bool,
+	// the option as it was spelled on the command-line,
+	// eg. '-o' for '--option'
+	raw : ~str,
+	// the position of the option in the parsed arguments
+	index : Option<uint>
 }
 
 /// Enum indicating whether a set of command-line arguments
 /// were parsed successfully
+#[deriving(Clone, Show)]
 pub enum ParseStatus {
 	/// The command line arguments were parsed successfully
 	Success,
 	/// An option to print usage information (eg. --help)
 	/// was used
 	Help,
+	/// An option to print the program version (eg. --version)
+	/// was used
+	Version,
 	/// An error occurred whilst parsing the arguments
 	Error
 }
 
+/// Whether an option which takes an optional argument was given
+/// and with what value, as returned by OptionParser::optional_value()
+#[deriving(Clone, Show)]
+pub enum OptionState {
+	/// The option was not given, or was negated after it was
+	/// last given
+	Absent,
+	/// The option was given without a value, eg. '--color'
+	Present,
+	/// The option was given with a value, eg. '--color=always'
+	PresentWith(~str)
+}
+
+/// An error for an option value which could not be converted
+/// to the requested type, as returned by OptionParser::value_as()
+#[deriving(Clone)]
+pub struct ValueError {
+	/// The long name of the option, eg. '--count'
+	pub option : ~str,
+	/// The value given for the option
+	pub value : ~str,
+	/// The position of the option in the parsed arguments, or
+	/// None if the value was not given on the command-line
+	/// (eg. if it is the option's default value)
+	pub index : Option<uint>,
+	/// A description of the error, from Messages::invalid_value
+	pub message : ~str
+}
+
+/// An iterator over the values of an option, as returned by
+/// OptionParser::values_iter()
+pub struct ValuesIter<'r> {
+	matches : slice::Items<'r, OptMatch>,
+	name : ~str
+}
+
+impl <'r> Iterator<&'r str> for ValuesIter<'r> {
+	fn next(&mut self) -> Option<&'r str> {
+		loop {
+			match self.matches.next() {
+				Some(opt_match) => {
+					let match_name : &str = opt_match.opt_name;
+					match opt_match.val {
+						Some(ref val) if match_name == self.name.as_slice() => return Some(val.as_slice()),
+						_ => ()
+					}
+				}
+				None => return None
+			}
+		}
+	}
+}
+
+/// An option or non-option argument from the command-line,
+/// as returned by OptionParser::tokens()
+#[deriving(Clone, Show)]
+pub enum Token {
+	/// An option, with its long name and value if it was given
+	/// one.  The negation of an option is given by its negated
+	/// name, eg. '--no-option'
+	OptionToken(~str, Option<~str>),
+	/// A non-option argument
+	PositionalToken(~str)
+}
+
 /// Holds the result of a call to OptionParser::parse(),
 /// storing information about matching command-line flags and
 /// the list of non-flag arguments on the command-line
+#[deriving(Clone)]
 pub struct ParseResult {
 	pub opts : Vec<OptMatch>,
 	pub status : ParseStatus,
-	pub args : Vec<~str>
+	pub args : Vec<~str>,
+	/// Error messages for problems encountered whilst
+	/// parsing the arguments.  This is empty unless status is
+	/// Error, or Help or Version if the help or version options
+	/// were used together with erroneous arguments.
+	pub errors : Vec<~str>,
+	/// Unknown options which were collected rather than
+	/// reported as errors, see OptionParser::unknown_short
+	/// and OptionParser::unknown_long
+	pub unknown : Vec<~str>,
+	/// Notes about arguments which were accepted but may not
+	/// have the intended effect, eg. 'verbose' given for
+	/// '--verbose'.  These do not affect the status
+	pub warnings : Vec<~str>,
+	// the options and non-option arguments in the order in
+	// which they were given
+	tokens : Vec<Token>,
+	// the usage information, if status is Help
+	help : Option<~str>
+}
+
+/// Word-wraps a string to fit @p width columns, for use in
+/// help text and messages which should be formatted
+/// consistently with --help output.
+///
+/// Lines after the first are indented to start at column
+/// @p start_col.  Paragraphs separated by blank lines are
+/// wrapped independently and the blank lines between them
+/// are preserved.
+pub fn wrap(s: &str, start_col : uint, width : uint) -> ~str {
+	let mut paragraphs : Vec<~str> = Vec::new();
+	let mut paragraph = StrBuf::new();
+	for line in s.lines() {
+		if line.trim().is_empty() {
+			if paragraph.len() > 0 {
+				paragraphs.push(wrap_paragraph(paragraph.as_slice(), start_col, width));
+				paragraph = StrBuf::new();
+			}
+		} else {
+			paragraph.push_str(line);
+			paragraph.push_char(' ');
+		}
+	}
+	if paragraph.len() > 0 {
+		paragraphs.push(wrap_paragraph(paragraph.as_slice(), start_col, width));
+	}
+
+	let mut separator = StrBuf::from_owned_str("\n\n".to_owned());
+	for _ in range(0, start_col) {
+		separator.push_char(' ');
+	}
+	paragraphs.connect(separator.as_slice())
 }
 
-// word-wraps a string to fit 'cols' columns.  Lines start at column
-// 'start_col'
-fn word_wrap_str(s: &str, start_col : uint, cols : uint) -> ~str {
+// word-wraps a single paragraph of text, treating all whitespace
+// in it as word breaks.  Words which are too long to fit on a line
+// are placed on a line of their own
+fn wrap_paragraph(s: &str, start_col : uint, width : uint) -> ~str {
 	let mut wrapped = StrBuf::new();
-	let mut line_spaces_left = cols - start_col;
-	let mut first_in_line = true;
+	let line_width = if width > start_col { width - start_col } else { 1 };
+	let mut line_len = 0;
 
 	for word in s.words() {
-		if line_spaces_left < word.len() {
+		let word_len = word.char_len();
+		if line_len > 0 && line_len + 1 + word_len > line_width {
 			wrapped.push_char('\n');
 			for _ in range(0, start_col) {
 				wrapped.push_char(' ');
 			}
-			line_spaces_left = cols - start_col;
-			first_in_line = true;
-		} else {
-			line_spaces_left -= word.len();
+			line_len = 0;
 		}
-		if first_in_line {
-			first_in_line = false;
-		} else {
+		if line_len > 0 {
 			wrapped.push_char(' ');
-			line_spaces_left -= 1;
+			line_len += 1;
 		}
 		wrapped.push_str(word);
+		line_len += word_len;
 	}
 
 	wrapped.into_owned()
 }
 
+// returns a description of each name shared by two options, which
+// therefore cannot both be registered with the same parser
+fn conflicts(a: &Opt, b: &Opt) -> Vec<~str> {
+	let mut errors = Vec::new();
+	if a.short.len() > 0 && a.short == b.short {
+		errors.push(format!("Options {} and {} have the same short form {}",
+		  a.long_parsed(), b.long_parsed(), a.short));
+	}
+	if a.long_parsed() == b.long_parsed() {
+		errors.push(format!("Option {} is defined more than once", a.long_parsed()));
+	}
+	errors
+}
+
+// checks the syntax of the short and long forms of an option,
+// returning a description of the problem if they are malformed
+fn syntax_error(short: &str, long: &str) -> Option<~str> {
+	let short_ok = short.len() == 0 ||
+	  (short.starts_with("-") && short.char_len() == 2 && !short.starts_with("--"));
+	if !short_ok {
+		return Some(format!("Invalid short option '{}' for {}, expected a '-' followed by a single letter",
+		  short, long))
+	}
+	let long_name = long.split(' ').take(1).next().unwrap();
+	if !long_name.starts_with("--") || long_name.len() < 3 {
+		return Some(format!("Invalid long option '{}', expected '--' followed by the option name", long))
+	}
+	let arg_spec = long.slice_from(long_name.len()).trim();
+	if arg_spec.contains("[") || arg_spec.contains("]") {
+		let is_optional_arg = arg_spec.starts_with("[") && arg_spec.ends_with("]") &&
+		  arg_spec.chars().filter(|c| *c == '[').count() == 1 &&
+		  arg_spec.chars().filter(|c| *c == ']').count() == 1;
+		if !is_optional_arg {
+			return Some(format!("Invalid argument syntax '{}' for option {}, expected 'ARG' for a required \
+			                     argument or '[ARG]' for an optional argument", arg_spec, long_name))
+		}
+	}
+	None
+}
+
+// returns the long form of an option name given with or
+// without the leading dashes
+fn long_name(name: &str) -> ~str {
+	if name.starts_with("--") {
+		name.to_owned()
+	} else {
+		format!("--{}", name)
+	}
+}
+
+// replaces each '{name}' placeholder in a message template with
+// the corresponding value.  Unknown placeholders are left as-is
+fn fill(template: &str, values: &[(&str, &str)]) -> ~str {
+	let mut filled = StrBuf::new();
+	let mut rest = template;
+	loop {
+		match rest.find('{') {
+			Some(start) => {
+				filled.push_str(rest.slice_to(start));
+				rest = rest.slice_from(start);
+				let mut substituted = false;
+				match rest.find('}') {
+					Some(end) => {
+						let name = rest.slice(1, end);
+						for &(key, value) in values.iter() {
+							if key == name {
+								filled.push_str(value);
+								substituted = true;
+								break
+							}
+						}
+						if substituted {
+							rest = rest.slice_from(end + 1);
+						}
+					}
+					None => ()
+				}
+				if !substituted {
+					filled.push_char('{');
+					rest = rest.slice_from(1);
+				}
+			}
+			None => {
+				filled.push_str(rest);
+				break
+			}
+		}
+	}
+	filled.into_owned()
+}
+
+// the column at which the long forms of options start in
+// --help output
+static LONG_NAME_COL : uint = 6;
+
+// the help width below which --help output uses the compact
+// layout, and the column at which descriptions start in it
+static COMPACT_HELP_WIDTH : uint = 40;
+static COMPACT_DESCRIPTION_COL : uint = 8;
+
+// the maximum edit distance between a mistyped option name or
+// value and a suggested alternative
+static MAX_SUGGESTION_DISTANCE : uint = 3;
+
+// the maximum number of options suggested for a mistyped
+// option name
+static MAX_SUGGESTIONS : uint = 3;
+
+// returns the name of a mistyped option for comparison with the
+// names of known options, ignoring leading dashes and a stray
+// trailing dash (eg. '---option' or '--option-')
+fn suggestion_name<'a>(input: &'a str) -> &'a str {
+	let name = input.trim_left_chars('-');
+	if name.ends_with("-") {
+		name.slice_to(name.len() - 1)
+	} else {
+		name
+	}
+}
+
+// the maximum edit distance between a non-option argument and an
+// option name for the argument to be reported as a possible option
+// missing its leading dashes.  This is kept low since most such
+// arguments are intended
+static MISSING_DASHES_DISTANCE : uint = 1;
+
+// returns the indices of the candidates which are no more than
+// 'threshold' edits away from 'input', with their edit distances,
+// closest first.  Candidates which are equally close keep their order
+fn ranked_matches(input: &str, candidates: &[&str], threshold: uint) -> Vec<(uint, uint)> {
+	let mut matches : Vec<(uint, uint)> = candidates.iter().enumerate().filter_map(|(index, candidate)| {
+		let edit_dist = candidate.lev_distance(input);
+		if edit_dist <= threshold {
+			Some((edit_dist, index))
+		} else {
+			None
+		}
+	}).collect();
+	matches.sort_by(|a, b| {
+		let (dist_a, _) = *a;
+		let (dist_b, _) = *b;
+		dist_a.cmp(&dist_b)
+	});
+	matches
+}
+
+// returns the candidate with the closest spelling to 'input', provided
+// that it is no more than 'threshold' edits away
+fn closest_match(input: &str, candidates: &[&str], threshold: uint) -> Option<~str> {
+	match ranked_matches(input, candidates, threshold).as_slice().head() {
+		Some(&(_, index)) => Some(candidates[index].to_owned()),
+		None => None
+	}
+}
+
+// parses a non-negative number with an optional single-letter
+// suffix which multiplies it, eg. '10M' or '1.5h', returning None
+// if the number or suffix are not valid.  Fractional results are
+// rounded down
+fn parse_with_suffix(val: &str, suffixes: &[(char, u64)]) -> Option<u64> {
+	let mut num_str = val;
+	let mut multiplier : u64 = 1;
+	match val.chars().last() {
+		Some(last) if !last.is_digit() && last != '.' => {
+			let mut found = false;
+			for &(suffix, value) in suffixes.iter() {
+				if suffix == last {
+					multiplier = value;
+					found = true;
+				}
+			}
+			if !found {
+				return None
+			}
+			num_str = val.slice_to(val.len() - 1);
+		}
+		_ => ()
+	}
+	match from_str::<f64>(num_str) {
+		Some(num) if num >= 0.0 => Some((num * multiplier as f64) as u64),
+		_ => None
+	}
+}
+
+// returns true if 'value' is non-empty and consists only of the
+// characters in 'pattern', a list of characters and ranges such
+// as 'A-Za-z0-9_'
+fn matches_pattern(pattern: &str, value: &str) -> bool {
+	let chars : Vec<char> = pattern.chars().collect();
+	let mut ranges = Vec::new();
+	let mut i = 0;
+	while i < chars.len() {
+		if i + 2 < chars.len() && *chars.get(i + 1) == '-' {
+			ranges.push((*chars.get(i), *chars.get(i + 2)));
+			i += 3;
+		} else {
+			ranges.push((*chars.get(i), *chars.get(i)));
+			i += 1;
+		}
+	}
+	!value.is_empty() && value.chars().all(|c| {
+		ranges.iter().any(|&(first, last)| c >= first && c <= last)
+	})
+}
+
+// splits a list-valued option's value into items separated by
+// commas or whitespace.  Items may be quoted with '"' to include
+// separators and a backslash escapes a quote or backslash.  Returns
+// None if the quotes are unbalanced
+fn split_list(value: &str) -> Option<Vec<~str>> {
+	let mut items = Vec::new();
+	let mut item = StrBuf::new();
+	// set if the current item contains quotes, so that an empty
+	// quoted item ('""') is kept
+	let mut quoted_item = false;
+	let mut in_quotes = false;
+	let mut escaped = false;
+	for c in value.chars() {
+		if escaped {
+			if c != '"' && c != '\\' {
+				item.push_char('\\');
+			}
+			item.push_char(c);
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if c == '"' {
+			in_quotes = !in_quotes;
+			quoted_item = true;
+		} else if !in_quotes && (c == ',' || c.is_whitespace()) {
+			if item.len() > 0 || quoted_item {
+				items.push(item.into_owned());
+				item = StrBuf::new();
+				quoted_item = false;
+			}
+		} else {
+			item.push_char(c);
+		}
+	}
+	if in_quotes {
+		return None
+	}
+	if escaped {
+		item.push_char('\\');
+	}
+	if item.len() > 0 || quoted_item {
+		items.push(item.into_owned());
+	}
+	Some(items)
+}
+
+// quotes a command-line argument for a POSIX shell if it is
+// empty or contains characters which the shell treats specially
+fn shell_quote(arg: &str) -> ~str {
+	let is_plain = arg.len() > 0 && arg.chars().all(|c| {
+		c.is_alphanumeric() || "-_=.,/:@%+".contains_char(c)
+	});
+	if is_plain {
+		return arg.to_owned()
+	}
+	let mut quoted = StrBuf::new();
+	quoted.push_char('\'');
+	for c in arg.chars() {
+		if c == '\'' {
+			quoted.push_str("'\\''");
+		} else {
+			quoted.push_char(c);
+		}
+	}
+	quoted.push_char('\'');
+	quoted.into_owned()
+}
+
+// quotes a string for inclusion in JSON output, escaping quotes,
+// backslashes and control characters
+fn json_str(s: &str) -> ~str {
+	let mut quoted = StrBuf::new();
+	quoted.push_char('"');
+	for c in s.chars() {
+		match c {
+			'"' => quoted.push_str("\\\""),
+			'\\' => quoted.push_str("\\\\"),
+			'\n' => quoted.push_str("\\n"),
+			'\r' => quoted.push_str("\\r"),
+			'\t' => quoted.push_str("\\t"),
+			c if c < ' ' => quoted.push_str(format!("\\u{:04x}", c as uint)),
+			c => quoted.push_char(c)
+		}
+	}
+	quoted.push_char('"');
+	quoted.into_owned()
+}
+
+impl OptMatch {
+	/// Returns the position in the list of arguments passed to
+	/// OptionParser::parse() at which this option was given, or
+	/// None if it was not given on the command-line (eg. if it was
+	/// read from a config file).  For an option whose value is given
+	/// as a separate argument, this is the position of the option.
+	pub fn index(&self) -> Option<uint> {
+		self.index
+	}
+
+	// returns true if this and 'other' are values of the same
+	// option from the same argument, eg. the values taken from
+	// the rest of the arguments by a consumes_rest option
+	fn same_use(&self, other: &OptMatch) -> bool {
+		self.opt_name == other.opt_name && self.index == other.index
+	}
+}
+
+impl fmt::Show for OptMatch {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.val {
+			_ if self.negated => write!(f.buf, "--no-{}", self.opt_name.slice_from(2)),
+			Some(ref val) => write!(f.buf, "{}={}", self.opt_name, *val),
+			None => write!(f.buf, "{}", self.opt_name)
+		}
+	}
+}
+
+impl fmt::Show for ValueError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self.index {
+			Some(index) => write!(f.buf, "{} (argument {})", self.message, index),
+			None => write!(f.buf, "{}", self.message)
+		}
+	}
+}
+
+impl fmt::Show for ParseResult {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let opts : Vec<~str> = self.opts.iter().map(|opt_match| {
+			format!("{}", *opt_match)
+		}).collect();
+		write!(f.buf, "status: {}, options: [{}], args: [{}]",
+		  self.status, opts.connect(", "), self.args.connect(", "))
+	}
+}
+
+impl ParseResult {
+	// records a non-option argument
+	fn push_arg(&mut self, arg: ~str) {
+		self.tokens.push(PositionalToken(arg.clone()));
+		self.args.push(arg);
+	}
+
+	// records a matched option
+	fn push_opt(&mut self, opt_match: OptMatch) {
+		let name = if opt_match.negated {
+			format!("--no-{}", opt_match.opt_name.slice_from(2))
+		} else {
+			opt_match.opt_name.clone()
+		};
+		self.tokens.push(OptionToken(name, opt_match.val.clone()));
+		self.opts.push(opt_match);
+	}
+
+	/// Returns the number of errors encountered whilst parsing
+	pub fn error_count(&self) -> uint {
+		self.errors.len()
+	}
+
+	/// Returns the first error encountered whilst parsing,
+	/// if any
+	pub fn first_error<'r>(&'r self) -> Option<&'r str> {
+		self.errors.as_slice().get(0).map(|error| error.as_slice())
+	}
+
+	/// Returns the usage information which was requested
+	/// (eg. with --help), if status is Help
+	pub fn help_text(&self) -> Option<~str> {
+		self.help.clone()
+	}
+
+	/// Combines another parse result into this one, eg. when global
+	/// options and subcommand options are parsed separately.
+	///
+	/// The options, arguments and errors from @p other are appended
+	/// to those of this result and the status becomes the more severe
+	/// of the two, in the order Error, Help, Version then Success.
+	/// Since OptionParser::value() returns the last value given for
+	/// an option, options given in both results take their value
+	/// from @p other.
+	pub fn merge(&mut self, other: ParseResult) {
+		self.status = match (self.status, other.status) {
+			(Error, _) | (_, Error) => Error,
+			(Help, _) | (_, Help) => Help,
+			(Version, _) | (_, Version) => Version,
+			_ => Success
+		};
+		self.opts.push_all_move(other.opts);
+		self.args.push_all_move(other.args);
+		self.errors.push_all_move(other.errors);
+		self.unknown.push_all_move(other.unknown);
+		self.warnings.push_all_move(other.warnings);
+		self.tokens.push_all_move(other.tokens);
+		if self.help.is_none() {
+			self.help = other.help;
+		}
+	}
+}
+
+impl Default for Messages {
+	fn default() -> Messages {
+		Messages {
+			unknown_option : "Unknown option {option}".to_owned(),
+			unknown_option_suggestion : "Unknown option {option}, did you mean '{suggestion}'?".to_owned(),
+			unknown_option_suggestions : "Unknown option {option}, did you mean one of: {suggestions}?".to_owned(),
+			ambiguous_option : "Option {option} is ambiguous, it could be: {candidates}".to_owned(),
+			unknown_subcommand : "Unknown command {command}".to_owned(),
+			ambiguous_subcommand : "Command {command} is ambiguous, it could be: {candidates}".to_owned(),
+			option_after_positional : "Options must come before arguments: {option} appeared after '{arg}'".to_owned(),
+			unexpected_argument : "Option {option} does not take an argument".to_owned(),
+			missing_option : "Option {option} is required".to_owned(),
+			missing_one_of : "One of the options {options} is required".to_owned(),
+			too_many_of_one_of : "Only one of the options {options} may be given (given {given})".to_owned(),
+			missing_argument : "Option {option} requires an argument but none was given.".to_owned(),
+			argument_is_option : "Option {option} requires an argument but was followed by the option {next}. \
+			                      A value starting with '-' can be given as eg. {option}=-value.".to_owned(),
+			missing_dashes : "note: did you mean the option {option}?".to_owned(),
+			too_few_arguments : "At least {min} {name} arguments are required (given {count})".to_owned(),
+			too_many_occurrences_once : "{option} may be given at most once (given {count} times)".to_owned(),
+			too_many_occurrences : "{option} may be given at most {max} times (given {count} times)".to_owned(),
+			invalid_choice : "Invalid value '{value}' for option {option}, expected one of: {choices}".to_owned(),
+			invalid_choice_suggestion : "Invalid value '{value}' for option {option}, did you mean '{suggestion}'?".to_owned(),
+			invalid_value : "Invalid value '{value}' for option {option}".to_owned(),
+			invalid_characters : "Value '{value}' for {option} contains invalid characters".to_owned(),
+			invalid_integer : "Invalid value '{value}' for option {option}, expected an integer".to_owned(),
+			invalid_number : "Invalid value '{value}' for option {option}, expected a number".to_owned(),
+			invalid_size : "Invalid value '{value}' for option {option}, expected a size such as \
+			                 '512', '10K' or '1.5M'".to_owned(),
+			invalid_duration : "Invalid value '{value}' for option {option}, expected a duration such as \
+			                     '30', '5m' or '1.5h'".to_owned(),
+			unbalanced_quotes : "Invalid value '{value}' for option {option}, unbalanced quotes".to_owned(),
+			path_not_found : "{option}: file '{value}' does not exist".to_owned(),
+			path_not_file : "{option}: '{value}' is not a file".to_owned(),
+			path_not_dir : "{option}: '{value}' is not a directory".to_owned(),
+			usage : "Usage: {program} {usage}".to_owned(),
+			example : "Example: {example}".to_owned(),
+			description_with_default : "{description} (default: {default})".to_owned(),
+			required_options : "Required options:".to_owned(),
+			optional_options : "Optional options:".to_owned(),
+			options_summary : "Options: {options}".to_owned(),
+			help_description : "Display usage information".to_owned(),
+			version_description : "Display version information".to_owned(),
+			config_unreadable : "Unable to read config file {path}: {error}".to_owned(),
+			config_syntax_error : "{location}: expected 'name = value', found '{line}'".to_owned(),
+			config_unknown_option : "{location}: unknown option {option}".to_owned(),
+			config_invalid_flag : "{location}: invalid value '{value}' for option {option}, \
+			                       expected 'true' or 'false'".to_owned()
+		}
+	}
+}
+
 impl Opt {
 	/// Returns true if a given argument string
 	/// (either in the form 'o' or '--option') matches
@@ -116,35 +1025,80 @@ impl Opt {
 	///
 	/// @p description Is a brief description of the option for use
 	/// in --help output
+	///
+	/// Fails if @p short or @p long are malformed.
 	pub fn new(short: &str, long: &str, description: &str) -> Opt {
+		match syntax_error(short, long) {
+			Some(error) => fail!("{}", error),
+			None => ()
+		}
+
 		Opt {
 			short : short.to_owned(),
 			long : long.to_owned(),
-			description : description.to_owned()
+			description : description.to_owned(),
+			example : None,
+			negatable : false,
+			choices : Vec::new(),
+			default : None,
+			arg_type : None,
+			check : None,
+			pattern : None,
+			max_occurrences : None,
+			consumes_rest : false,
+			trim : false,
+			required : false,
+			hidden : false
 		}
 	}
 
 	/// Returns an Opt struct for the '--help' option
 	pub fn help_opt() -> Opt {
-		Opt {
-			short : "-h".to_owned(),
-			long : "--help".to_owned(),
-			description : "Display usage information".to_owned()
-		}
+		Opt::new("-h", "--help", "Display usage information")
 	}
 
 	/// Returns an Opt struct for a --version flag
 	pub fn version_opt() -> Opt {
-		Opt {
-			short : "-v".to_owned(),
-			long : "--version".to_owned(),
-			description : "Display version information".to_owned()
+		Opt::new("-v", "--version", "Display version information")
+	}
+
+	/// Returns the long form of this option as displayed
+	/// in --help output, eg. '--[no-]option' for negatable
+	/// options
+	fn long_help(&self) -> ~str {
+		let name = if self.negatable {
+			format!("--[no-]{}", self.long_parsed().slice_from(2))
+		} else {
+			self.long_parsed().to_owned()
+		};
+		match self.metavar() {
+			Some(metavar) => format!("{} {}", name, metavar),
+			None => name
+		}
+	}
+
+	/// Returns the name of this option's argument as displayed
+	/// in --help output, eg. 'ARG' for '--option ARG' or '[ARG]'
+	/// for '--option [ARG]'.  If 'long' does not name the argument,
+	/// a name is derived from the argument type.
+	pub fn metavar(&self) -> Option<~str> {
+		if self.long.contains(" ") {
+			Some(self.long.slice_from(self.long_parsed().len() + 1).trim().to_owned())
+		} else {
+			self.arg_type.map(|arg_type| {
+				match arg_type {
+					StrArg => "VALUE",
+					IntArg => "N",
+					FloatArg => "NUM",
+					PathArg => "FILE"
+				}.to_owned()
+			})
 		}
 	}
 
 	/// Returns true if this option takes an argument
 	pub fn has_arg(&self) -> bool {
-		self.long.contains(" ")
+		self.long.contains(" ") || self.arg_type.is_some()
 	}
 
 	/// Returns true if this option takes a mandatory argument
@@ -161,18 +1115,198 @@ impl <'a> OptionParser<'a> {
 			usage : usage.to_owned(),
 			banner : banner.to_owned(),
 			opts : opts.to_owned(),
-			tail_banner : None
+			tail_banner : None,
+			help_width : 80,
+			description_col : 26,
+			compact_help : false,
+			auto_help : true,
+			help_opt : None,
+			show_defaults : false,
+			epilog : None,
+			program_name : None,
+			group_by_required : false,
+			print_usage_on_error : false,
+			decimal_sizes : false,
+			quiet : false,
+			defaults : HashMap::new(),
+			version : None,
+			show_version_in_help : false,
+			auto_version : false,
+			variadic_name : None,
+			variadic_min : 0,
+			subcommands : Vec::new(),
+			abbreviate_subcommands : false,
+			required_one_of : Vec::new(),
+			value_separators : vec!('='),
+			short_options_enabled : true,
+			options_before_positionals : false,
+			terminator : "--".to_owned(),
+			trailing_target : None,
+			stop_at_unknown : false,
+			unknown_short : RejectUnknown,
+			unknown_long : RejectUnknown,
+			messages : Default::default(),
+			handlers : Vec::new()
+		}
+	}
+
+	/// Declares a trailing variadic argument called @p name, eg.
+	/// 'FILES', which collects the remaining non-option arguments.
+	/// These can be retrieved with variadic() and the argument is
+	/// shown in the usage line of --help output as eg. 'FILES...'.
+	///
+	/// parse() reports an error if fewer than @p min arguments
+	/// are given.
+	pub fn set_variadic(&mut self, name: &str, min: uint) {
+		self.variadic_name = Some(name.to_owned());
+		self.variadic_min = min;
+	}
+
+	/// Makes the arguments after a '--' terminator values of @p opt,
+	/// eg. so that 'mytool run -- child --child-flag' gives 'child'
+	/// and '--child-flag' as values of a '--command' option.  These
+	/// can be retrieved with values() rather than from ParseResult::args.
+	/// The values are kept exactly as given, without the trimming,
+	/// escapes or checks applied to other values of @p opt
+	pub fn set_trailing_target(&mut self, opt: &Opt) {
+		self.trailing_target = Some(opt.long_parsed().to_owned());
+	}
+
+	/// Requires exactly one of @p opts to be given, eg. one of
+	/// '--json', '--xml' or '--yaml' for an output format.  parse()
+	/// reports an error if none of them or more than one is given
+	pub fn add_required_one_of(&mut self, opts: &[&Opt]) {
+		self.required_one_of.push(opts.iter().map(|opt| opt.long_parsed().to_owned()).collect());
+	}
+
+	/// Sets the function which run() invokes when @p opt is given,
+	/// replacing any previous handler for it.  The handler is passed
+	/// the option's value, or None if it was given without one
+	pub fn set_handler(&mut self, opt: &Opt, handler: |Option<&str>|: 'a) {
+		let name = opt.long_parsed().to_owned();
+		self.handlers.retain(|&(ref handler_name, _)| *handler_name != name);
+		self.handlers.push((name, handler));
+	}
+
+	/// Parses a list of command-line arguments in the same way as
+	/// parse(), then invokes the handler set with set_handler() for
+	/// each option given, in command-line order, and returns the
+	/// non-option arguments.  An option given several times invokes
+	/// its handler once per occurrence, except that options unset by
+	/// a later '--no-<name>' argument are skipped.  The handler of a
+	/// consumes_rest option is passed all of its values, separated by
+	/// spaces and quoted if necessary, eg. 'ls -la /tmp' for
+	/// '--exec ls -la /tmp'.
+	///
+	/// If parsing fails or usage or version information was requested,
+	/// no handlers are invoked and None is returned.
+	pub fn run(&mut self, args: ~[~str]) -> Option<Vec<~str>> {
+		let result = self.parse(args);
+		match result.status {
+			Success => (),
+			_ => return None
+		}
+		for (index, opt_match) in result.opts.iter().enumerate() {
+			let name : &str = opt_match.opt_name;
+			let unset = opt_match.negated || result.opts.slice_from(index + 1).iter().any(|later| {
+				let later_name : &str = later.opt_name;
+				later.negated && later_name == name
+			});
+			if unset {
+				continue
+			}
+			// the values of a consumes_rest option are passed to its
+			// handler together, quoted in the same way as by
+			// normalized_command_line()
+			let val = if self.consumes_rest(name) {
+				if index > 0 && result.opts.get(index - 1).same_use(opt_match) {
+					continue
+				}
+				let vals : Vec<~str> = result.opts.slice_from(index).iter().take_while(|other| {
+					other.same_use(opt_match)
+				}).filter_map(|other| other.val.as_ref().map(|val| shell_quote(*val))).collect();
+				if vals.is_empty() {
+					None
+				} else {
+					Some(vals.connect(" "))
+				}
+			} else {
+				opt_match.val.clone()
+			};
+			for entry in self.handlers.mut_iter() {
+				let (ref handler_name, ref mut handler) = *entry;
+				if handler_name.as_slice() == name {
+					(*handler)(val.as_ref().map(|val| val.as_slice()));
+				}
+			}
+		}
+		Some(result.args)
+	}
+
+	/// Adds the options of @p other to this parser, eg. for a
+	/// core parser to take in the options contributed by plugins
+	/// before parsing.  The added options are included in --help
+	/// output in the same way as the parser's own options.
+	///
+	/// If any of the options share a short or long name with one of
+	/// this parser's options, including its built-in --help and
+	/// --version options, or with another of the added options, none
+	/// of them are added and a description of each conflict is returned.
+	pub fn extend(&mut self, other: &OptionParser<'a>) -> Result<(), Vec<~str>> {
+		let builtin_opts = self.builtin_opts();
+		let mut existing_opts : Vec<&Opt> = Vec::new();
+		for opt in self.opts.iter() {
+			existing_opts.push(*opt);
 		}
+		for opt in builtin_opts.iter() {
+			existing_opts.push(opt);
+		}
+
+		let mut errors = Vec::new();
+		for opt in other.opts.iter() {
+			for existing in existing_opts.iter() {
+				errors.push_all_move(conflicts(*existing, *opt));
+			}
+			existing_opts.push(*opt);
+		}
+		if errors.len() > 0 {
+			return Err(errors)
+		}
+		self.opts = self.opts.iter().chain(other.opts.iter()).map(|opt| *opt).collect();
+		Ok(())
+	}
+
+	/// Sets the version of the program and adds a '-v/--version'
+	/// option which prints it.  When the option is used, parse()
+	/// prints '<program name> <version>' and returns a result
+	/// with the Version status
+	pub fn enable_version(&mut self, version: &str) {
+		self.version = Some(version.to_owned());
+		self.auto_version = true;
+	}
+
+	/// Replaces the '-h/--help' option which prints usage
+	/// information, eg. so that '-h' can be used for another
+	/// option.  Usage information is printed when @p opt is used
+	/// in the same way as for --help.
+	pub fn set_help_opt(&mut self, opt: Opt) {
+		self.help_opt = Some(opt);
+		self.auto_help = true;
 	}
 
-	/// Returns a list of option flags in a command-line argument
-	fn opts_in_arg(arg : &'a str) -> Vec<&'a str> {
+	/// Returns a list of option flags in a command-line argument.
+	/// A lone '-' is not an option, by convention it is an
+	/// argument referring to stdin.  If short options are disabled,
+	/// only arguments starting with '--' contain options.
+	/// Each character, rather than byte, in a group of short
+	/// options is a separate option.
+	fn opts_in_arg<'r>(&self, arg : &'r str) -> Vec<&'r str> {
 		let mut opts = Vec::new();
 		if arg.starts_with("--") {
 			opts.push(arg);
-		} else if arg.starts_with("-") {
-			for i in range(1, arg.len()) {
-				opts.push(arg.slice(i, i+1));
+		} else if self.short_options_enabled && arg.starts_with("-") && arg.len() > 1 {
+			for (i, c) in arg.char_indices().skip(1) {
+				opts.push(arg.slice(i, i + c.len_utf8_bytes()));
 			}
 		}
 		opts
@@ -184,181 +1318,1301 @@ impl <'a> OptionParser<'a> {
 	/// and return a ParseResult indicating the options
 	/// that were set.
 	///
+	/// The first element of @p args is assumed to be the
+	/// program name, as returned by os::args(), and is skipped.
+	/// Use parse_iter() for argument lists which do not
+	/// start with the program name.
+	///
+	/// The value of an option which takes an argument may either
+	/// follow it as a separate argument (eg. '--option value')
+	/// or, for long options, be attached with '=', eg. '--option=value'.
+	/// An argument which follows an option and looks like an option
+	/// itself is not taken as its value, unless it is a number such
+	/// as '-5'.  A value starting with a dash can instead be attached,
+	/// or given as a separate argument by escaping the dash with a
+	/// backslash, eg. '--message \-WIP-' sets the value '-WIP-'.
+	/// Long options may be abbreviated to any unambiguous prefix of
+	/// their name, eg. '--out' for '--output'.
+	///
+	/// Short options may be grouped, eg. '-abc' is equivalent to
+	/// '-a -b -c'.  Each option in the group is recorded separately,
+	/// so a letter which is repeated (eg. '-vv') counts as two uses
+	/// of its option.  An option in a group which requires an argument
+	/// takes the rest of the group as its value, optionally after '=',
+	/// so '-vo=out.txt' and '-voout.txt' both set '-v' and give '-o'
+	/// the value 'out.txt'.  Letters after such an option are part of
+	/// its value, not options, so '-ov' gives '-o' the value 'v'.  An
+	/// option followed only by '=' (eg. '-o=' or '-vo=') is given an
+	/// empty value, in the same way as '--output='.
+	///
+	/// A '--' argument, or the parser's terminator if it has been
+	/// changed, ends option parsing.  All arguments which follow it
+	/// are treated as non-option arguments, or as values of the
+	/// option given to set_trailing_target().  If the terminator has
+	/// been changed, a '--' argument is a non-option argument.
+	///
 	/// Successfully parsed options can be retrieved using
 	/// is_set(), value() or with_value() on the result.
+	///
+	/// Fails if the parser's options conflict, see validate().
 	pub fn parse(&self, args: ~[~str]) -> ParseResult {
-		let mut result = ParseResult {
-			opts : Vec::new(),
-			status : Success,
-			args : Vec::new()
-		};
+		self.parse_slice(args)
+	}
 
-		let mut opts : Vec<&Opt> = vec!();
-		for opt in self.opts.iter() {
-			opts.push(*opt);
+	/// Parse a list of command-line arguments in the same way as
+	/// parse(), after transforming them with @p preprocess, eg. to
+	/// expand user-defined aliases such as 'co' for 'checkout --quiet'.
+	///
+	/// @p preprocess is called once with the whole argument list,
+	/// including the program name, and its result is parsed instead.
+	/// Since parse() skips the first argument, the preprocessor should
+	/// leave the program name in place.
+	pub fn parse_with_preprocessor(&self, args: ~[~str], preprocess: |~[~str]| -> ~[~str]) -> ParseResult {
+		self.parse(preprocess(args))
+	}
+
+	/// Parse a list of command-line arguments in the same way
+	/// as parse(), but without taking ownership of them.
+	pub fn parse_slice(&self, args: &[~str]) -> ParseResult {
+		let result = self.parse_args(args, true, None, &mut Vec::new());
+		self.report(&result);
+		result
+	}
+
+	/// Parse a sequence of command-line arguments in the same
+	/// way as parse(), except that every argument is processed,
+	/// ie. the program name is expected to have been removed.
+	pub fn parse_iter<I: Iterator<~str>>(&self, mut args: I) -> ParseResult {
+		let args : Vec<~str> = args.collect();
+		let result = self.parse_args(args.as_slice(), false, None, &mut Vec::new());
+		self.report(&result);
+		result
+	}
+
+	/// Parse a list of command-line arguments in the same way as
+	/// parse(), taking the values of options which are not given on
+	/// the command-line from a config file.
+	///
+	/// The config file contains one 'name = value' line per option,
+	/// where 'name' is the long name of the option with or without
+	/// the leading dashes.  Options which do not take an argument are
+	/// given a value of 'true' or 'false'.  Blank lines and lines
+	/// starting with '#' are ignored.
+	///
+	/// Values from the config file take precedence over the default
+	/// values of options, but not over values given on the command-line.
+	/// Values are checked in the same way as command-line values and
+	/// problems with the file are reported as errors in the result.
+	pub fn parse_with_config(&self, args: ~[~str], config_path: &Path) -> ParseResult {
+		let result = self.parse_args(args, true, Some(config_path), &mut Vec::new());
+		self.report(&result);
+		result
+	}
+
+	/// Parse a list of command-line arguments in the same way as
+	/// parse(), but instead of printing errors, pass each of them
+	/// in turn to @p handler, eg. to format and log them in the same
+	/// way as the program's other errors.  The status of the result
+	/// is still Error if the handler was called.
+	///
+	/// Warnings, usage and version information are printed as for parse().
+	pub fn parse_with_error_handler(&self, args: ~[~str], handler: |&str|) -> ParseResult {
+		let result = self.parse_args(args, true, None, &mut Vec::new());
+		match result.status {
+			Error => {
+				self.report_warnings(&result);
+				for error in result.errors.iter() {
+					handler(*error);
+				}
+			},
+			_ => self.report(&result)
 		}
-		let help_opt = Opt::help_opt();
-		opts.push(&help_opt);
+		result
+	}
 
-		let mut had_error = false;
-		let mut skip_next_arg = false;
-		for (index, arg) in args.iter().enumerate() {
-			if skip_next_arg {
-				skip_next_arg = false;
+	// adds the option values from a config file to a parse result,
+	// for options which were not given on the command-line.  The
+	// config values are placed before any command-line values
+	fn read_config(&self, path: &Path, result: &mut ParseResult) {
+		let display_path = format!("{}", path.display());
+		let contents = match File::open(path).read_to_str() {
+			Ok(contents) => contents,
+			Err(err) => {
+				result.errors.push(fill(self.messages.config_unreadable,
+				  [("path", display_path.as_slice()), ("error", format!("{}", err).as_slice())]));
+				return
+			}
+		};
+
+		let mut config_opts = Vec::new();
+		for (index, line) in contents.lines().enumerate() {
+			let line = line.trim();
+			if line.is_empty() || line.starts_with("#") {
+				continue
+			}
+			let location = format!("{}:{}", display_path, index + 1);
+			let (name, val) = match line.find('=') {
+				Some(pos) => (line.slice_to(pos).trim(), line.slice_from(pos + 1).trim()),
+				None => {
+					result.errors.push(fill(self.messages.config_syntax_error,
+					  [("location", location.as_slice()), ("line", line)]));
+					continue
+				}
+			};
+			let long = long_name(name);
+			let opt = match self.opts.iter().find(|opt| opt.long_parsed() == long.as_slice()) {
+				Some(opt) => *opt,
+				None => {
+					result.errors.push(fill(self.messages.config_unknown_option,
+					  [("location", location.as_slice()), ("option", long.as_slice())]));
+					continue
+				}
+			};
+			let on_command_line = result.opts.iter().any(|opt_match| {
+				let match_name : &str = opt_match.opt_name;
+				match_name == opt.long_parsed()
+			});
+			if on_command_line {
 				continue
 			}
 
-			let mut is_opt = false;
-			for opt_arg in OptionParser::opts_in_arg(*arg).iter() {
-				is_opt = true;
-				let matching_opt = opts.iter().find(|opt| {
-					opt.match_arg(*opt_arg)
-				});
-				match matching_opt {
-					Some(opt) => {
-						let has_arg =
-						  opt.has_arg() &&
-						  index < args.len()-1 &&
-						  (arg.starts_with("--") || arg.len() == 2);
-						if has_arg {
-							skip_next_arg = true;
-							result.opts.push(OptMatch {
-								opt_name : opt.long_parsed().to_owned(),
-								val : args[index+1].clone()
-							});
-						} else {
-							if opt.has_required_arg() {
-								if !had_error {
-									println!("Missing required argument for option {}.\n\n{}\n", opt_arg, OptionParser::arg_help_str(*opt));
-									had_error = true;
-								}
-							} else {
-								result.opts.push(OptMatch {
-									opt_name : opt.long_parsed().to_owned(),
-									val : "".to_owned()
+			let (val, negated) = if opt.has_arg() {
+				match self.check_value(opt, val) {
+					Some(error) => {
+						result.errors.push(format!("{}: {}", location, error));
+						continue
+					}
+					None => (Some(val), false)
+				}
+			} else {
+				match val {
+					"true" => (None, false),
+					"false" => (None, true),
+					_ => {
+						result.errors.push(fill(self.messages.config_invalid_flag,
+						  [("location", location.as_slice()), ("option", opt.long_parsed()), ("value", val)]));
+						continue
+					}
+				}
+			};
+			config_opts.push(OptMatch {
+				opt_name : opt.long_parsed().to_owned(),
+				val : val.map(|val| val.to_owned()),
+				negated : negated,
+				raw : name.to_owned(),
+				index : None
+			});
+		}
+
+		for (index, opt_match) in config_opts.move_iter().enumerate() {
+			result.opts.insert(index, opt_match);
+		}
+	}
+
+	/// Returns the non-option arguments in a list of command-line
+	/// arguments, as they would be returned by parse() in
+	/// ParseResult::args, but without printing anything.  The first
+	/// argument is assumed to be the program name and is skipped.
+	///
+	/// If the parser has no options and is otherwise configured as
+	/// by new(), option arguments are simply skipped without being
+	/// matched against the built-in options.
+	pub fn positionals_only(&self, args: ~[~str]) -> Vec<~str> {
+		let passes_through_unknown = match (self.unknown_short, self.unknown_long) {
+			(PassThroughUnknown, _) | (_, PassThroughUnknown) => true,
+			_ => false
+		};
+		// the simple scan below only gives the same result as parse()
+		// if none of the options which affect the arguments are set
+		let needs_parse = self.opts.len() > 0 || self.stop_at_unknown || passes_through_unknown ||
+		  !self.short_options_enabled || self.trailing_target.is_some() ||
+		  self.subcommands.len() > 0 || self.terminator.as_slice() != "--";
+		if needs_parse {
+			return self.parse_args(args, true, None, &mut Vec::new()).args
+		}
+		let mut positionals = Vec::new();
+		let mut after_terminator = false;
+		for arg in args.iter().skip(1) {
+			if !after_terminator && *arg == self.terminator {
+				after_terminator = true;
+			} else if after_terminator || !arg.starts_with("-") || arg.as_slice() == "-" {
+				positionals.push(arg.clone());
+			}
+		}
+		positionals
+	}
+
+	/// Returns a description of how each of a list of command-line
+	/// arguments is interpreted by parse(), one argument per line,
+	/// eg. '-v -> option --verbose' or 'foo -> positional arg #0'.
+	///
+	/// This is intended for debugging command lines which do not have
+	/// the expected effect.  Unlike parse(), nothing is printed.
+	pub fn explain(&self, args: ~[~str]) -> ~str {
+		let mut trace = Vec::new();
+		self.parse_args(args, true, None, &mut trace);
+		trace.connect("\n")
+	}
+
+	/// Checks the parser's options for problems, returning a
+	/// description of each problem found.  This checks that:
+	///
+	///  - The short and long forms of each option are well-formed,
+	///    as for Opt::new(), since they may have been changed since
+	///    the option was created.
+	///  - No two options share a short or long name, including the
+	///    names of the automatically added help and version options.
+	///  - The default value of an option with a list of choices
+	///    is one of them.
+	///  - The options in each group passed to add_required_one_of()
+	///    and the trailing target option are registered.
+	///
+	/// These are programming errors rather than user errors, so
+	/// parse() fails if the options are not valid.
+	pub fn validate(&self) -> Result<(), Vec<~str>> {
+		let mut opts : Vec<&Opt> = vec!();
+		for opt in self.opts.iter() {
+			opts.push(*opt);
+		}
+		let builtin_opts = self.builtin_opts();
+		for opt in builtin_opts.iter() {
+			opts.push(opt);
+		}
+
+		let mut errors = Vec::new();
+		for opt in opts.iter() {
+			match syntax_error(opt.short, opt.long) {
+				Some(error) => errors.push(error),
+				None => ()
+			}
+			match opt.default {
+				Some(ref default) if opt.choices.len() > 0 && !opt.choices.contains(default) => {
+					errors.push(format!("The default value '{}' of option {} is not one of its choices",
+					  *default, opt.long_parsed()));
+				}
+				_ => ()
+			}
+		}
+		for (index, opt) in opts.iter().enumerate() {
+			for other in opts.slice_from(index + 1).iter() {
+				errors.push_all_move(conflicts(*opt, *other));
+			}
+		}
+		let names : Vec<&str> = opts.iter().map(|opt| opt.long_parsed()).collect();
+		for group in self.required_one_of.iter() {
+			for name in group.iter() {
+				if !names.contains(&name.as_slice()) {
+					errors.push(format!("Option {} in a group passed to add_required_one_of() is not registered", *name));
+				}
+			}
+		}
+		match self.trailing_target {
+			Some(ref name) if !names.contains(&name.as_slice()) => {
+				errors.push(format!("The trailing target option {} is not registered", *name));
+			}
+			_ => ()
+		}
+		if errors.len() > 0 {
+			Err(errors)
+		} else {
+			Ok(())
+		}
+	}
+
+	/// Returns the options which the parser matches arguments
+	/// against.  If @p include_builtin is true, this includes the
+	/// automatically added help and version options if they are
+	/// enabled.
+	///
+	/// Since the built-in options are created when parsing, the
+	/// returned options are copies of those registered.
+	pub fn options(&self, include_builtin: bool) -> Vec<Opt> {
+		let mut opts : Vec<Opt> = self.opts.iter().map(|opt| (*opt).clone()).collect();
+		if include_builtin {
+			opts.push_all_move(self.builtin_opts());
+		}
+		opts
+	}
+
+	// returns the options which are added to the parser's own
+	// options, ie. --help and --version if they are enabled
+	fn builtin_opts(&self) -> Vec<Opt> {
+		let mut opts = Vec::new();
+		if self.auto_help {
+			opts.push(self.builtin_help_opt());
+		}
+		if self.auto_version {
+			opts.push(Opt::new("-v", "--version", self.messages.version_description));
+		}
+		opts
+	}
+
+	// returns the option which prints usage information
+	fn builtin_help_opt(&self) -> Opt {
+		match self.help_opt {
+			Some(ref opt) => opt.clone(),
+			None => Opt::new("-h", "--help", self.messages.help_description)
+		}
+	}
+
+	// parses a list of command-line arguments without printing
+	// anything, adding a description of how each argument was
+	// interpreted to 'trace'.  Option values are also read from
+	// 'config_path', if given, before checking the result.
+	// Fails if the options are not valid
+	fn parse_args(&self, args: &[~str], skip_program_name: bool, config_path: Option<&Path>,
+	              trace: &mut Vec<~str>) -> ParseResult {
+		match self.validate() {
+			Err(errors) => fail!("Invalid options: {}", errors.connect(", ")),
+			Ok(()) => ()
+		}
+
+		let mut result = ParseResult {
+			opts : Vec::new(),
+			status : Success,
+			args : Vec::new(),
+			errors : Vec::new(),
+			unknown : Vec::new(),
+			warnings : Vec::new(),
+			tokens : Vec::new(),
+			help : None
+		};
+
+		let mut opts : Vec<&Opt> = vec!();
+		for opt in self.opts.iter() {
+			opts.push(*opt);
+		}
+		let builtin_opts = self.builtin_opts();
+		for opt in builtin_opts.iter() {
+			opts.push(opt);
+		}
+
+		let trailing_opt = match self.trailing_target {
+			Some(ref name) => opts.iter().find(|opt| opt.long_parsed() == name.as_slice()).map(|opt| *opt),
+			None => None
+		};
+
+		let mut skip_next_arg = false;
+		let mut after_terminator = false;
+		let mut terminator_index = None;
+		for (index, arg) in args.iter().enumerate() {
+			if skip_program_name && index == 0 {
+				trace.push(format!("{} -> program name", *arg));
+				continue
+			}
+			if skip_next_arg {
+				skip_next_arg = false;
+				continue
+			}
+
+			// only the first terminator ends option parsing, any later
+			// ones are passed through as ordinary arguments, or
+			// taken as values of the trailing target option
+			if after_terminator {
+				match trailing_opt {
+					// the values are kept exactly as given, since they are
+					// typically passed on to another program
+					Some(opt) => {
+						trace.push(format!("{} -> option {} with value '{}'", *arg, opt.long_parsed(), *arg));
+						result.push_opt(OptMatch {
+							opt_name : opt.long_parsed().to_owned(),
+							val : Some(arg.clone()),
+							negated : false,
+							raw : self.terminator.clone(),
+							index : terminator_index
+						});
+					}
+					None => {
+						trace.push(format!("{} -> positional arg \\#{}", *arg, result.args.len()));
+						result.push_arg(arg.clone());
+					}
+				}
+				continue
+			}
+			if *arg == self.terminator {
+				trace.push(format!("{} -> end of options", *arg));
+				after_terminator = true;
+				terminator_index = Some(index);
+				continue
+			}
+			// a '--' which is not the terminator is never an option
+			if arg.as_slice() == "--" {
+				trace.push(format!("{} -> positional arg \\#{}", *arg, result.args.len()));
+				result.push_arg(arg.clone());
+				continue
+			}
+
+			let mut is_opt = false;
+			let mut stopped = false;
+			let mut consumed_rest = false;
+			let opt_args = self.opts_in_arg(*arg);
+			// the help and version options may still be given after
+			// a non-option argument, eg. 'prog file.txt --help'
+			let is_builtin = opt_args.len() == 1 && self.builtin_opts().iter().any(|opt| {
+				opt.match_arg(*opt_args.get(0))
+			});
+			if self.options_before_positionals && opt_args.len() > 0 && result.args.len() > 0 && !is_builtin {
+				let last_arg = result.args.last().unwrap().clone();
+				trace.push(format!("{} -> option after positional arg '{}'", *arg, last_arg));
+				result.errors.push(fill(self.messages.option_after_positional,
+				  [("option", arg.as_slice()), ("arg", last_arg.as_slice())]));
+				continue
+			}
+			// the byte offset of the rest of a group of short options
+			// after the current one
+			let mut rest_pos = 1;
+			for (opt_index, opt_arg) in opt_args.iter().enumerate() {
+				is_opt = true;
+				// only the last option in a group of short options
+				// (eg. 'f' in '-vf') may take the next argument as its value
+				let last_in_arg = opt_index == opt_args.len() - 1;
+				let is_long = arg.starts_with("--");
+				rest_pos += opt_arg.len();
+				// the value of a long option may be attached with '=',
+				// eg. '--option=value', or another of the value separators.
+				// The value is everything after the first separator
+				let separator_pos = opt_arg.find(|c: char| self.value_separators.contains(&c));
+				let (opt_name, long_val) = match separator_pos {
+					Some(pos) if is_long => {
+						let value_pos = opt_arg.char_range_at(pos).next;
+						(opt_arg.slice_to(pos), Some(opt_arg.slice_from(value_pos)))
+					}
+					_ => (*opt_arg, None)
+				};
+				let spelling = if is_long {
+					opt_name.to_owned()
+				} else {
+					format!("-{}", opt_name)
+				};
+				let label = if opt_args.len() > 1 {
+					format!("{} (in {})", spelling, *arg)
+				} else {
+					spelling.clone()
+				};
+				// long options may be abbreviated to any unambiguous
+				// prefix of their name, eg. '--out' for '--output'.
+				// An empty name (eg. in '--=foo') is not a prefix
+				let mut prefix_matches : Vec<&Opt> = Vec::new();
+				let matching_opt = match opts.iter().find(|opt| opt.match_arg(opt_name)) {
+					Some(opt) => Some(*opt),
+					None if is_long && opt_name.len() > 2 => {
+						prefix_matches = opts.iter().filter(|opt| {
+							opt.long_parsed().starts_with(opt_name)
+						}).map(|opt| *opt).collect();
+						if prefix_matches.len() == 1 {
+							Some(*prefix_matches.get(0))
+						} else {
+							None
+						}
+					}
+					None => None
+				};
+				// the rest of a group of short options may be attached
+				// with '=' as the value of the option before it, eg.
+				// '-vo=out.txt'.  An option which requires an argument
+				// takes the rest of the group as its value without one
+				// (eg. '-voout.txt') and a numeric value may be attached
+				// to any option which takes an argument, eg. '-j4' for '-j 4'
+				let explicit_val = if is_long {
+					long_val
+				} else if !last_in_arg && arg.slice_from(rest_pos).starts_with("=") {
+					Some(arg.slice_from(rest_pos + 1))
+				} else {
+					None
+				};
+				let attached_val = if explicit_val.is_some() || last_in_arg {
+					explicit_val
+				} else {
+					let rest = arg.slice_from(rest_pos);
+					let takes_rest = match matching_opt {
+						Some(opt) => opt.has_required_arg(),
+						None => false
+					};
+					if takes_rest || rest.chars().all(|c| c.is_digit()) {
+						Some(rest)
+					} else {
+						None
+					}
+				};
+				let negated_opt = if matching_opt.is_none() && opt_name.starts_with("--no-") {
+					let name = format!("--{}", opt_name.slice_from(5));
+					opts.iter().find(|opt| {
+						opt.negatable && opt.long_parsed() == name.as_slice()
+					})
+				} else {
+					None
+				};
+				match matching_opt {
+					Some(opt) if opt.consumes_rest => {
+						let rest = args.slice_from(index + 1);
+						for val in attached_val.iter() {
+							self.add_value(&mut result, trace, label.clone(), opt, *val, spelling.clone(), index);
+						}
+						// the rest of the arguments are kept exactly as given,
+						// like the values of the trailing target
+						for val in rest.iter() {
+							trace.push(format!("{} -> option {} with value '{}'", *val, opt.long_parsed(), *val));
+							result.push_opt(OptMatch {
+								opt_name : opt.long_parsed().to_owned(),
+								val : Some(val.clone()),
+								negated : false,
+								raw : spelling.clone(),
+								index : Some(index)
+							});
+						}
+						if attached_val.is_none() && rest.is_empty() {
+							if opt.has_required_arg() {
+								trace.push(format!("{} -> option {}, missing its required argument",
+								  label, opt.long_parsed()));
+								let error = fill(self.messages.missing_argument, [("option", opt.long_parsed())]);
+								result.errors.push(format!("{}\n\n{}", error, self.arg_help_str(opt)));
+							} else {
+								trace.push(format!("{} -> option {}", label, opt.long_parsed()));
+								result.push_opt(OptMatch {
+									opt_name : opt.long_parsed().to_owned(),
+									val : None,
+									negated : false,
+									raw : spelling.clone(),
+									index : Some(index)
+								});
+							}
+						}
+						consumed_rest = true;
+						break
+					},
+					Some(opt) if opt.has_arg() && attached_val.is_some() => {
+						self.add_value(&mut result, trace, label, opt, attached_val.unwrap(), spelling, index);
+						break
+					},
+					Some(opt) if !opt.has_arg() && explicit_val.is_some() => {
+						trace.push(format!("{} -> option {}, which does not take an argument", label, opt.long_parsed()));
+						let error = fill(self.messages.unexpected_argument, [("option", opt.long_parsed())]);
+						result.errors.push(format!("{}\n\n{}", error, self.arg_help_str(opt)));
+						break
+					},
+					Some(opt) => {
+						let next_arg = if opt.has_arg() && last_in_arg && long_val.is_none() {
+							args.get(index + 1)
+						} else {
+							None
+						};
+						// a following argument which looks like an option is not
+						// taken as the value, unless it is a number (eg. '-5')
+						let next_is_opt = match next_arg {
+							Some(next) => self.opts_in_arg(*next).len() > 0 && from_str::<f64>(*next).is_none(),
+							None => false
+						};
+						if next_arg.is_some() && !next_is_opt {
+							skip_next_arg = true;
+							self.add_value(&mut result, trace, label, opt, next_arg.unwrap().as_slice(), spelling, index);
+						} else {
+							if opt.has_required_arg() {
+								let error = if next_is_opt {
+									trace.push(format!("{} -> option {}, followed by an option instead of its argument",
+									  label, opt.long_parsed()));
+									fill(self.messages.argument_is_option,
+									  [("option", opt.long_parsed()), ("next", next_arg.unwrap().as_slice())])
+								} else {
+									trace.push(format!("{} -> option {}, missing its required argument",
+									  label, opt.long_parsed()));
+									fill(self.messages.missing_argument, [("option", opt.long_parsed())])
+								};
+								result.errors.push(format!("{}\n\n{}", error, self.arg_help_str(opt)));
+							} else {
+								trace.push(format!("{} -> option {}", label, opt.long_parsed()));
+								result.push_opt(OptMatch {
+									opt_name : opt.long_parsed().to_owned(),
+									val : None,
+									negated : false,
+									raw : spelling,
+									index : Some(index)
 								});
 							}
 						};
 					},
+					None if negated_opt.is_some() && long_val.is_some() => {
+						let opt = negated_opt.unwrap();
+						trace.push(format!("{} -> negation of option {}, which does not take an argument",
+						  label, opt.long_parsed()));
+						let error = fill(self.messages.unexpected_argument, [("option", spelling.as_slice())]);
+						result.errors.push(format!("{}\n\n{}", error, self.arg_help_str(*opt)));
+					},
+					None if negated_opt.is_some() => {
+						let opt = negated_opt.unwrap();
+						trace.push(format!("{} -> negation of option {}", label, opt.long_parsed()));
+						result.push_opt(OptMatch {
+							opt_name : opt.long_parsed().to_owned(),
+							val : None,
+							negated : true,
+							raw : spelling,
+							index : Some(index)
+						});
+					},
+					None if prefix_matches.len() > 1 => {
+						trace.push(format!("{} -> ambiguous option", label));
+						let candidates : Vec<&str> = prefix_matches.iter().map(|opt| {
+							opt.long_parsed()
+						}).collect();
+						result.errors.push(fill(self.messages.ambiguous_option,
+						  [("option", opt_name), ("candidates", candidates.connect(", ").as_slice())]));
+					},
+					None if self.stop_at_unknown => {
+						trace.push(format!("{} -> unknown option, end of options", label));
+						stopped = true;
+						break
+					},
 					None => {
-						if !had_error {
-							match self.suggest_opt(*arg) {
-								Some(opt) => {
-									println!("Unknown option {}, did you mean '{}'?\n\n{}\n",
-									  opt_arg,
-									  opt.long_parsed(),
-									  OptionParser::arg_help_str(opt))
-								}
-								None => {
-									println!("Unknown option {}", opt_arg);
+						// an unknown long option is kept whole, including
+						// any attached value
+						let policy = if is_long { self.unknown_long } else { self.unknown_short };
+						let token = if is_long { (*opt_arg).to_owned() } else { spelling.clone() };
+						match policy {
+							PassThroughUnknown => {
+								trace.push(format!("{} -> unknown option, passed through as positional arg \\#{}",
+								  label, result.args.len()));
+								result.push_arg(token);
+							}
+							CollectUnknown => {
+								trace.push(format!("{} -> unknown option, collected", label));
+								result.unknown.push(token);
+							}
+							RejectUnknown => {
+								trace.push(format!("{} -> unknown option", label));
+								// if several options are equally close to the
+								// mistyped name, they are all suggested.  A single
+								// letter is matched against the short forms instead
+								let close_opts = if is_long && suggestion_name(opt_name).char_len() > 1 {
+									self.close_opts(opt_name, MAX_SUGGESTIONS)
+								} else {
+									Vec::new()
+								};
+								let no_clear_match = close_opts.len() > 1 && {
+									let (first_dist, _) = *close_opts.get(0);
+									let (second_dist, _) = *close_opts.get(1);
+									first_dist == second_dist
+								};
+								// a group of short options is matched as a whole,
+								// eg. '-output' for '--output', in which case the
+								// group is named in the message and the rest of it
+								// is not reported separately
+								let whole_group = !is_long && opt_args.len() > 1;
+								let mut suggested = false;
+								let error = if no_clear_match {
+									let names : Vec<&str> = close_opts.iter().map(|candidate| {
+										let (_, opt) = *candidate;
+										opt.long_parsed()
+									}).collect();
+									fill(self.messages.unknown_option_suggestions,
+									  [("option", spelling.as_slice()), ("suggestions", names.connect(", ").as_slice())])
+								} else {
+									match self.suggest_opt(if is_long { opt_name } else { *arg }) {
+										Some((opt, suggestion)) => {
+											suggested = true;
+											let option = if whole_group { arg.as_slice() } else { spelling.as_slice() };
+											let error = fill(self.messages.unknown_option_suggestion,
+											  [("option", option), ("suggestion", suggestion)]);
+											format!("{}\n\n{}", error, self.arg_help_str(opt))
+										}
+										None => {
+											fill(self.messages.unknown_option, [("option", spelling.as_slice())])
+										}
+									}
+								};
+								result.errors.push(error);
+								if whole_group && suggested {
+									break
 								}
 							}
-							had_error = true;
 						}
 					}
 				}
 			}
 
-			if !is_opt && index > 0 {
-				result.args.push(arg.clone());
+			if consumed_rest {
+				break
+			}
+			if stopped {
+				for rest in args.slice_from(index).iter() {
+					trace.push(format!("{} -> positional arg \\#{}", *rest, result.args.len()));
+					result.push_arg(rest.clone());
+				}
+				break
+			}
+
+			if !is_opt {
+				trace.push(format!("{} -> positional arg \\#{}", *arg, result.args.len()));
+				// an argument which is spelled like the name of one of
+				// the parser's own options was probably meant as that
+				// option, unless it is also the name of a subcommand
+				let names : Vec<&str> = self.opts.iter().filter(|opt| !opt.hidden).map(|opt| {
+					opt.long_parsed().slice_from(2)
+				}).collect();
+				let intended = if self.subcommands.contains(arg) {
+					None
+				} else {
+					closest_match(*arg, names.as_slice(), MISSING_DASHES_DISTANCE)
+				};
+				match intended {
+					Some(name) => {
+						let option = long_name(name.as_slice());
+						result.warnings.push(fill(self.messages.missing_dashes,
+						  [("arg", arg.as_slice()), ("option", option.as_slice())]));
+					}
+					None => ()
+				}
+				result.push_arg(arg.clone());
 			}
 		}
 
-		if had_error {
-			result.status = Error;
-		} else {
-			// handle built-in options
-			if self.is_set(&result, &help_opt) {
-				self.print_usage();
-				result.status = Help;
+		match config_path {
+			Some(path) => self.read_config(path, &mut result),
+			None => ()
+		}
+
+		if self.subcommands.len() > 0 && result.args.len() > 0 {
+			self.resolve_subcommand(&mut result);
+		}
+
+		match self.variadic_name {
+			Some(ref name) if result.args.len() < self.variadic_min => {
+				result.errors.push(fill(self.messages.too_few_arguments, [("name", name.as_slice()),
+				  ("min", self.variadic_min.to_str().as_slice()),
+				  ("count", result.args.len().to_str().as_slice())]));
 			}
+			_ => ()
+		}
+
+		for opt in opts.iter() {
+			if opt.required && !self.is_set(&result, *opt) {
+				result.errors.push(fill(self.messages.missing_option, [("option", opt.long_parsed())]));
+			}
+			match opt.max_occurrences {
+				Some(max) => {
+					// a '--no-<name>' argument is not a use of the option.
+					// The values taken from the rest of the arguments by a
+					// consumes_rest option or the trailing target all
+					// belong to one use of it
+					let mut indices : Vec<Option<uint>> = result.opts.iter().filter(|opt_match| {
+						let name : &str = opt_match.opt_name;
+						name == opt.long_parsed() && !opt_match.negated
+					}).map(|opt_match| opt_match.index).collect();
+					let is_trailing_target = match self.trailing_target {
+						Some(ref name) => name.as_slice() == opt.long_parsed(),
+						None => false
+					};
+					if opt.consumes_rest || is_trailing_target {
+						indices.dedup();
+					}
+					let count = indices.len();
+					if count > max {
+						let template = if max == 1 {
+							self.messages.too_many_occurrences_once.as_slice()
+						} else {
+							self.messages.too_many_occurrences.as_slice()
+						};
+						result.errors.push(fill(template, [("option", opt.long_parsed()),
+						  ("max", max.to_str().as_slice()), ("count", count.to_str().as_slice())]));
+					}
+				}
+				None => ()
+			}
+		}
+
+		for group in self.required_one_of.iter() {
+			let given : Vec<&str> = group.iter().filter(|name| {
+				self.is_set_by_name(&result, **name)
+			}).map(|name| name.as_slice()).collect();
+			let names : Vec<&str> = group.iter().map(|name| name.as_slice()).collect();
+			if given.len() == 0 {
+				result.errors.push(fill(self.messages.missing_one_of, [("options", names.connect(", ").as_slice())]));
+			} else if given.len() > 1 {
+				result.errors.push(fill(self.messages.too_many_of_one_of,
+				  [("options", names.connect(", ").as_slice()), ("given", given.connect(", ").as_slice())]));
+			}
+		}
+
+		// the help and version options take precedence over
+		// errors, since they are used when looking for guidance
+		if self.wants_help(&result) {
+			result.status = Help;
+			result.help = Some(self.format_help_str());
+		} else if self.wants_version(&result) {
+			result.status = Version;
+		} else if result.errors.len() > 0 {
+			result.status = Error;
 		}
 
 		result
 	}
 
+	// checks that the first non-option argument in a parse result
+	// names a subcommand, replacing an abbreviated name with the
+	// full name if abbreviations are allowed
+	fn resolve_subcommand(&self, result : &mut ParseResult) {
+		let command = result.args.get(0).clone();
+		if self.subcommands.contains(&command) {
+			return
+		}
+		let candidates : Vec<&str> = if self.abbreviate_subcommands {
+			self.subcommands.iter().filter(|name| {
+				name.starts_with(command)
+			}).map(|name| name.as_slice()).collect()
+		} else {
+			Vec::new()
+		};
+		match candidates.len() {
+			0 => result.errors.push(fill(self.messages.unknown_subcommand, [("command", command.as_slice())])),
+			1 => *result.args.get_mut(0) = (*candidates.get(0)).to_owned(),
+			_ => result.errors.push(fill(self.messages.ambiguous_subcommand,
+			  [("command", command.as_slice()), ("candidates", candidates.connect(", ").as_slice())]))
+		}
+	}
+
+	// prints the errors or usage information for a parse result,
+	// unless the parser is in quiet mode.  Errors and warnings
+	// are printed to stderr
+	fn report(&self, result: &ParseResult) {
+		if self.quiet {
+			return
+		}
+		self.report_warnings(result);
+		match result.status {
+			Error => {
+				let _ = stdio::stderr().write_str(self.error_report(result));
+			},
+			Help => self.print_usage(),
+			Version => match self.version_line() {
+				Some(line) => println(line),
+				None => ()
+			},
+			Success => ()
+		}
+	}
+
+	// returns the line printed for the version option, eg.
+	// 'program 1.2.3', or None if the version is not set
+	fn version_line(&self) -> Option<~str> {
+		self.version.as_ref().map(|version| format!("{} {}", self.program(), *version))
+	}
+
+	// returns the text printed to stderr for a parse result with
+	// errors, which is the first error followed by the usage line
+	// if print_usage_on_error is set
+	fn error_report(&self, result: &ParseResult) -> ~str {
+		let mut report = StrBuf::new();
+		report.push_str(format!("{}\n\n", result.errors.get(0)));
+		if self.print_usage_on_error {
+			report.push_str(self.usage_line());
+			report.push_char('\n');
+		}
+		report.into_owned()
+	}
+
+	// prints the warnings for a parse result to stderr, so that
+	// they are kept apart from the program's own output, unless
+	// the parser is in quiet mode
+	fn report_warnings(&self, result: &ParseResult) {
+		if self.quiet {
+			return
+		}
+		let mut stderr = stdio::stderr();
+		for warning in result.warnings.iter() {
+			let _ = stderr.write_line(*warning);
+		}
+	}
+
 	/// Prints usage information for the command-line options.
 	/// This has the same effect as passing the -h flag
 	pub fn print_usage(&self) {
-		print(self.format_help_str());
+		println(self.format_help_str());
 	}
 
-	fn arg_help_str(opt: &Opt) -> ~str {
-		let mut help_str = if opt.short.len() > 0 {
-			StrBuf::from_owned_str(format!("  {}, {}", opt.short, opt.long))
-		} else {
-			StrBuf::from_owned_str(format!("      {}", opt.long))
-		};
+	fn arg_help_str(&self, opt: &Opt) -> ~str {
+		self.option_help(opt, self.description_col, self.help_width)
+	}
 
-		let description_col = 26;
-		let first_line_len;
+	/// Returns the --help output for a single option, with its
+	/// description starting at column @p description_col and
+	/// wrapped to @p width columns.  This can be used to build
+	/// custom help layouts which are consistent with format_help_str().
+	///
+	/// As with the parser's own --help output, the compact layout
+	/// is used if compact_help is set or @p width is less than 40.
+	pub fn option_help(&self, opt: &Opt, description_col: uint, width: uint) -> ~str {
+		// the short and long forms are aligned in separate columns,
+		// so that the long forms line up whether or not options
+		// have a short form
+		let mut help_str = StrBuf::from_owned_str("  ".to_owned());
+		if opt.short.len() > 0 {
+			help_str.push_str(opt.short);
+			help_str.push_str(", ");
+		}
+		while help_str.as_slice().char_len() < LONG_NAME_COL {
+			help_str.push_char(' ');
+		}
+		help_str.push_str(opt.long_help());
 
-		if help_str.len() < description_col {
-			first_line_len = help_str.len();
+		// the description starts on the same line as the option flags
+		// if there is room for at least one space between them,
+		// otherwise it starts at the description column on the next
+		// line.  In compact mode, it always starts on the next line
+		let compact = self.compact_help || width < COMPACT_HELP_WIDTH;
+		let description_col = if compact { COMPACT_DESCRIPTION_COL } else { description_col };
+		let flags_len = help_str.as_slice().char_len();
+		let padding = if !compact && flags_len < description_col {
+			description_col - flags_len
 		} else {
-			help_str.push_str("\n");
-			first_line_len = 0;
+			help_str.push_char('\n');
+			description_col
+		};
+		for _ in range(0, padding) {
+			help_str.push_char(' ');
 		}
 
-		for _ in range(first_line_len, description_col) {
-			help_str.push_str(" ");
+		let description = match self.default_value(opt) {
+			Some(default) if self.show_defaults => {
+				fill(self.messages.description_with_default,
+				  [("description", opt.description.as_slice()), ("default", default)])
+			}
+			_ => opt.description.clone()
+		};
+		help_str.push_str(wrap(description, description_col, width));
+
+		match opt.example {
+			Some(ref example) => {
+				help_str.push_char('\n');
+				for _ in range(0, description_col) {
+					help_str.push_char(' ');
+				}
+				help_str.push_str(wrap(fill(self.messages.example, [("example", example.as_slice())]), description_col, width));
+			}
+			None => ()
 		}
 
-		help_str.push_str(word_wrap_str(opt.description, description_col, 80));
 		help_str.into_owned()
 	}
 
+	// returns the program name to display in --help output
+	fn program(&self) -> ~str {
+		match self.program_name {
+			Some(ref name) => name.clone(),
+			None => {
+				let args = os::args();
+				if args.len() > 0 {
+					args[0].clone()
+				} else {
+					"program".to_owned()
+				}
+			}
+		}
+	}
+
+	// replaces the '{program}' and '{version}' placeholders in
+	// the usage string or a banner.  '{version}' is left as-is if
+	// the version is not set
+	fn expand(&self, text: &str) -> ~str {
+		let program = self.program();
+		match self.version {
+			Some(ref version) => fill(text, [("program", program.as_slice()), ("version", version.as_slice())]),
+			None => fill(text, [("program", program.as_slice())])
+		}
+	}
+
+	/// Returns the usage line of --help output, eg.
+	/// 'Usage: program [options]'
+	pub fn usage_line(&self) -> ~str {
+		let mut usage = StrBuf::from_owned_str(self.expand(self.usage));
+		match self.variadic_name {
+			Some(ref name) => {
+				if usage.len() > 0 {
+					usage.push_char(' ');
+				}
+				usage.push_str(*name);
+				usage.push_str("...");
+			}
+			None => ()
+		}
+		fill(self.messages.usage,
+		  [("program", self.program().as_slice()), ("usage", usage.as_slice())])
+	}
+
+	/// Returns a one-line summary of the options which are not
+	/// hidden, including the built-in ones, eg.
+	/// 'Options: -h --help -o --output', for a terse alternative
+	/// to the --help output or for embedding in a larger header
+	pub fn options_summary(&self) -> ~str {
+		let mut flags = Vec::new();
+		for opt in self.options(true).iter().filter(|opt| !opt.hidden) {
+			if opt.short.len() > 0 {
+				flags.push(opt.short.clone());
+			}
+			flags.push(opt.long_parsed().to_owned());
+		}
+		fill(self.messages.options_summary, [("options", flags.connect(" ").as_slice())])
+	}
+
 	/// Returns a string containing the --help output
-	/// for the current set of arguments
+	/// for the current set of arguments.  The string does
+	/// not end with a newline.  Sections which are empty, such
+	/// as the usage line if 'usage' is empty and there is no
+	/// variadic argument, are left out.
 	pub fn format_help_str(&self) -> ~str {
-		let usage_str : &str = format!("Usage: {} {}", os::args()[0], self.usage);
+		let usage_str = self.usage_line();
 
 		struct OptHelpEntry<'a> {
 			help_str : ~str,
-			sort_key : &'a str
+			sort_key : &'a str,
+			required : bool
 		};
 
-		let mut opt_list : Vec<OptHelpEntry> = self.opts.iter().map(|opt| {
+		let mut opt_list : Vec<OptHelpEntry> = self.opts.iter().filter(|opt| !opt.hidden).map(|opt| {
 			OptHelpEntry {
-				help_str : OptionParser::arg_help_str(*opt),
-				sort_key : opt.long
+				help_str : self.arg_help_str(*opt),
+				sort_key : opt.long,
+				required : opt.required
 			}
 		}).collect();
 		opt_list.sort_by(|a,b| {
 			a.sort_key.cmp(&b.sort_key)
 		});
 
-		let banner : &str = word_wrap_str(self.banner, 0, 80);
-		let opt_help_list : Vec<~str> = opt_list.iter().map(|entry| {
-			entry.help_str.clone()
-		}).collect();
-		let opt_help_text : &str = opt_help_list.connect("\n");
-		let mut sections = vec!(usage_str, banner, opt_help_text);
+		// the banners span the full width of the help output,
+		// independently of the option description column
+		let banner = wrap(self.expand(self.banner), 0, self.help_width);
+		let opt_help_text = if self.group_by_required {
+			let groups = [(true, self.messages.required_options.as_slice()),
+			              (false, self.messages.optional_options.as_slice())];
+			let mut group_texts = Vec::new();
+			for &(required, heading) in groups.iter() {
+				let group : Vec<~str> = opt_list.iter().filter(|entry| {
+					entry.required == required
+				}).map(|entry| entry.help_str.clone()).collect();
+				if group.len() > 0 {
+					group_texts.push(format!("{}\n{}", heading, group.connect("\n")));
+				}
+			}
+			group_texts.connect("\n\n")
+		} else {
+			let opt_help_list : Vec<~str> = opt_list.iter().map(|entry| {
+				entry.help_str.clone()
+			}).collect();
+			opt_help_list.connect("\n")
+		};
+		let mut sections = Vec::new();
+		match self.version_line() {
+			Some(line) if self.show_version_in_help => sections.push(line),
+			_ => ()
+		}
+		// the usage line is left out if there is nothing to add
+		// to the program name
+		if self.usage.len() > 0 || self.variadic_name.is_some() {
+			sections.push(usage_str);
+		}
+		sections.push(banner);
+		sections.push(opt_help_text);
 
 		match self.tail_banner {
-			Some(ref _tail) => {
-				let tail : &str = *_tail;
-				sections.push(tail)
+			Some(ref tail) => {
+				sections.push(wrap(self.expand(*tail), 0, self.help_width))
 			}
 			None => ()
 		}
 
-		sections.connect("\n\n").append("\n")
+		match self.epilog {
+			Some(ref epilog) => {
+				// keep the indentation of each line, so that
+				// example commands can be set apart from the text
+				let lines : Vec<~str> = epilog.lines().map(|line| {
+					let text = line.trim_left();
+					let indent = line.len() - text.len();
+					let mut wrapped = StrBuf::new();
+					for _ in range(0, indent) {
+						wrapped.push_char(' ');
+					}
+					wrapped.push_str(wrap(text, indent, self.help_width));
+					wrapped.into_owned()
+				}).collect();
+				sections.push(lines.connect("\n"))
+			}
+			None => ()
+		}
+
+		// empty sections, eg. a parser with no banner, are skipped
+		// rather than leaving blank paragraphs in the output
+		let sections : Vec<~str> = sections.move_iter().filter(|section| {
+			!section.trim().is_empty()
+		}).collect();
+		sections.connect("\n\n")
+	}
+
+	/// Returns a JSON description of the command-line interface,
+	/// eg. for editor integrations and documentation tools.  This
+	/// is an object with 'program', 'usage' and 'banner' strings and
+	/// an 'options' array, including the built-in options but not
+	/// hidden ones.  Each option has 'short' and 'metavar' strings,
+	/// which are null if the option has none, 'long' and 'description'
+	/// strings and 'takes_arg', 'required' and 'option_required'
+	/// booleans, where 'required' is true if the option's argument is
+	/// mandatory and 'option_required' is true if the option itself
+	/// must be given
+	pub fn help_json(&self) -> ~str {
+		let opts : Vec<~str> = self.options(true).iter().filter(|opt| !opt.hidden).map(|opt| {
+			let short = if opt.short.len() > 0 { json_str(opt.short) } else { "null".to_owned() };
+			let metavar = match opt.metavar() {
+				Some(metavar) => json_str(metavar),
+				None => "null".to_owned()
+			};
+			let mut json = StrBuf::new();
+			json.push_str("{\"short\": ");
+			json.push_str(short);
+			json.push_str(", \"long\": ");
+			json.push_str(json_str(opt.long_parsed()));
+			json.push_str(", \"metavar\": ");
+			json.push_str(metavar);
+			json.push_str(", \"description\": ");
+			json.push_str(json_str(opt.description));
+			json.push_str(", \"takes_arg\": ");
+			json.push_str(opt.has_arg().to_str());
+			json.push_str(", \"required\": ");
+			json.push_str(opt.has_required_arg().to_str());
+			json.push_str(", \"option_required\": ");
+			json.push_str(opt.required.to_str());
+			json.push_char('}');
+			json.into_owned()
+		}).collect();
+
+		let mut json = StrBuf::new();
+		json.push_str("{\"program\": ");
+		json.push_str(json_str(self.program()));
+		json.push_str(", \"usage\": ");
+		json.push_str(json_str(self.expand(self.usage)));
+		json.push_str(", \"banner\": ");
+		json.push_str(json_str(self.expand(self.banner)));
+		json.push_str(", \"options\": [");
+		json.push_str(opts.connect(", "));
+		json.push_str("]}");
+		json.into_owned()
 	}
 
 	// for a given input argument string, returns the registered
-	// option with the closest spelling
-	fn suggest_opt<'a>(&'a self, input : &str) -> Option<&'a Opt> {
-		let mut min_edit_dist : uint = Bounded::max_value();
-		let mut suggested_opt : Option<&'a Opt> = None;
-		for opt in self.opts.iter() {
-			let edit_dist = opt.long_parsed().lev_distance(input);
-			if edit_dist < min_edit_dist {
-				min_edit_dist = edit_dist;
-				suggested_opt = Some(*opt);
+	// option with the closest spelling and the spelling to suggest.
+	// Leading dashes and a stray trailing dash are ignored so that
+	// only the option names are compared.  A single letter is matched
+	// against the short forms, so that eg. '--o' suggests '-o'.
+	// Hidden options are never suggested
+	fn suggest_opt<'a>(&'a self, input : &str) -> Option<(&'a Opt, &'a str)> {
+		let input_name = suggestion_name(input);
+		if input_name.char_len() == 1 {
+			let short = format!("-{}", input_name);
+			match self.opts.iter().find(|opt| !opt.hidden && opt.short == short) {
+				Some(opt) => return Some((*opt, opt.short.as_slice())),
+				None => ()
+			}
+		}
+		match self.close_opts(input, 1).as_slice().head() {
+			Some(&(_, opt)) => Some((opt, opt.long_parsed())),
+			None => None
+		}
+	}
+
+	/// Returns up to @p n of the registered options with the
+	/// closest spelling to a mistyped option name @p input, closest
+	/// first.  Options whose names differ from @p input by more
+	/// than a few edits are not included, nor are hidden options.
+	pub fn suggest_opts<'a>(&'a self, input : &str, n : uint) -> Vec<&'a Opt> {
+		self.close_opts(input, n).iter().map(|candidate| {
+			let (_, opt) = *candidate;
+			opt
+		}).collect()
+	}
+
+	// returns up to 'n' of the options with the closest long names
+	// to a mistyped option name, with their edit distances, closest
+	// first.  Options which are equally close keep their order
+	fn close_opts<'a>(&'a self, input : &str, n : uint) -> Vec<(uint, &'a Opt)> {
+		let opts : Vec<&'a Opt> = self.opts.iter().filter(|opt| !opt.hidden).map(|opt| *opt).collect();
+		let names : Vec<&str> = opts.iter().map(|opt| opt.long_parsed().trim_left_chars('-')).collect();
+		let mut matches = ranked_matches(suggestion_name(input), names.as_slice(), MAX_SUGGESTION_DISTANCE);
+		matches.truncate(n);
+		matches.iter().map(|candidate| {
+			let (edit_dist, index) = *candidate;
+			(edit_dist, *opts.get(index))
+		}).collect()
+	}
+
+	// records a value given for an option at position 'arg_index'
+	// in the arguments in a parse result, or an error if the value
+	// is not acceptable.  The value is trimmed if the option
+	// requires it, then a backslash escaping a leading dash
+	// (eg. '\-x') is removed
+	fn add_value(&self, result : &mut ParseResult, trace : &mut Vec<~str>, label : ~str,
+	             opt : &Opt, val : &str, spelling : ~str, arg_index : uint) {
+		let val = if opt.trim { val.trim() } else { val };
+		let val = if val.starts_with("\\-") { val.slice_from(1) } else { val };
+		match self.check_value(opt, val) {
+			Some(error) => {
+				trace.push(format!("{} -> option {}, with invalid value '{}'", label, opt.long_parsed(), val));
+				result.errors.push(error)
+			}
+			None => {
+				trace.push(format!("{} -> option {} with value '{}'", label, opt.long_parsed(), val));
+				result.push_opt(OptMatch {
+					opt_name : opt.long_parsed().to_owned(),
+					val : Some(val.to_owned()),
+					negated : false,
+					raw : spelling,
+					index : Some(arg_index)
+				});
+			}
+		}
+	}
+
+	// checks that a value given for an option is acceptable,
+	// returning an error message if not
+	fn check_value(&self, opt : &Opt, val : &str) -> Option<~str> {
+		if opt.choices.len() > 0 && !opt.choices.iter().any(|choice| choice.as_slice() == val) {
+			let choices : Vec<&str> = opt.choices.iter().map(|choice| choice.as_slice()).collect();
+			return match closest_match(val, choices.as_slice(), MAX_SUGGESTION_DISTANCE) {
+				Some(choice) => Some(fill(self.messages.invalid_choice_suggestion,
+				  [("option", opt.long_parsed()), ("value", val), ("suggestion", choice.as_slice())])),
+				None => Some(fill(self.messages.invalid_choice,
+				  [("option", opt.long_parsed()), ("value", val), ("choices", choices.connect(", ").as_slice())]))
+			}
+		}
+		match opt.pattern {
+			Some(ref pattern) if !matches_pattern(*pattern, val) => {
+				return Some(fill(self.messages.invalid_characters, [("option", opt.long_parsed()), ("value", val)]))
+			}
+			_ => ()
+		}
+		let type_error = match opt.arg_type {
+			Some(IntArg) if from_str::<int>(val).is_none() => Some(self.messages.invalid_integer.as_slice()),
+			Some(FloatArg) if from_str::<f64>(val).is_none() => Some(self.messages.invalid_number.as_slice()),
+			_ => None
+		};
+		if type_error.is_some() {
+			return type_error.map(|template| {
+				fill(template, [("option", opt.long_parsed()), ("value", val)])
+			})
+		}
+		match opt.check {
+			Some(check) => {
+				let path = Path::new(val);
+				match check {
+					PathExists if !path.exists() => {
+						Some(fill(self.messages.path_not_found, [("option", opt.long_parsed()), ("value", val)]))
+					}
+					PathIsFile if !path.is_file() => {
+						Some(fill(self.messages.path_not_file, [("option", opt.long_parsed()), ("value", val)]))
+					}
+					PathIsDir if !path.is_dir() => {
+						Some(fill(self.messages.path_not_dir, [("option", opt.long_parsed()), ("value", val)]))
+					}
+					_ => None
+				}
 			}
+			None => None
 		}
-		suggested_opt
 	}
 
 	/// Invokes action() with the value of a given option if it was set
@@ -369,34 +2623,1738 @@ impl <'a> OptionParser<'a> {
 		}
 	}
 
-	/// Returns the value for a given option if set or None otherwise
-	pub fn value<'r>(&self, flags : &'r ParseResult, match_opt: &Opt) -> Option<&'r str> {
-		let matches = self.values(flags, match_opt);
-		if matches.len() > 0 {
-			Some(matches[0])
-		} else {
-			None
+	/// Returns the value for a given option if set, its default
+	/// value if it has one or None otherwise.  A default set with
+	/// set_default() takes precedence over the option's own default.
+	/// If the option was given more than once, the last value is
+	/// returned.  An option given without a value (eg. '--flag') has
+	/// no value, whereas an explicitly empty value (eg. '--name=' or
+	/// '--name ""') is returned as Some("")
+	pub fn value<'r>(&'r self, flags : &'r ParseResult, match_opt: &'r Opt) -> Option<&'r str> {
+		match self.values_named(flags, match_opt.long_parsed()).last() {
+			Some(val) => Some(*val),
+			None => self.default_value(match_opt)
 		}
 	}
 
-	/// Returns all of the values for a given option
-	pub fn values<'r>(&self, flags : &'r ParseResult, match_opt: &Opt) -> ~[&'r str] {
-		let mut matches = Vec::new();
+	/// Returns the value for a given option if it was given, or
+	/// otherwise the result of @p default, which is used in place
+	/// of the option's default value.  @p default is only called if
+	/// the option was not given, so it can compute a default which is
+	/// expensive or depends on the values of other options.
+	pub fn value_or_else(&self, flags : &ParseResult, opt: &Opt, default: || -> ~str) -> ~str {
+		match self.values_named(flags, opt.long_parsed()).last() {
+			Some(val) => (*val).to_owned(),
+			None => default()
+		}
+	}
+
+	/// Sets the default value of an option, which is returned by
+	/// value() if the option is not given.  Unlike Opt::default,
+	/// this can be computed when the parser is set up, eg. from the
+	/// current directory.  It takes precedence over Opt::default.
+	pub fn set_default(&mut self, opt: &Opt, value: &str) {
+		self.defaults.insert(opt.long_parsed().to_owned(), value.to_owned());
+	}
+
+	// returns the default value of an option, as set with
+	// set_default() or otherwise by the option itself
+	fn default_value<'r>(&'r self, opt: &'r Opt) -> Option<&'r str> {
+		match self.defaults.find(&opt.long_parsed().to_owned()) {
+			Some(default) => Some(default.as_slice()),
+			None => opt.default.as_ref().map(|default| default.as_slice())
+		}
+	}
+
+	/// Returns the value for a given option converted to type T,
+	/// an error if the value cannot be converted or None if the
+	/// option was not set and has no default value.  The error
+	/// records which option the value came from and where it
+	/// appeared on the command-line, for use in diagnostics
+	pub fn value_as<T: FromStr>(&self, flags : &ParseResult, opt: &Opt) -> Option<Result<T, ValueError>> {
+		let last_match = self.matches_named(flags, opt.long_parsed()).move_iter().filter(|opt_match| {
+			opt_match.val.is_some()
+		}).last();
+		let (val, index) = match last_match {
+			Some(opt_match) => (opt_match.val.get_ref().as_slice(), opt_match.index),
+			None => match self.default_value(opt) {
+				Some(default) => (default, None),
+				None => return None
+			}
+		};
+		Some(match from_str::<T>(val) {
+			Some(converted) => Ok(converted),
+			None => Err(ValueError {
+				option : opt.long_parsed().to_owned(),
+				value : val.to_owned(),
+				index : index,
+				message : fill(self.messages.invalid_value, [("option", opt.long_parsed()), ("value", val)])
+			})
+		})
+	}
+
+	/// Returns a level for an option which can either be repeated or
+	/// given a number, eg. a verbosity set with '-vv' or '--verbose 2'.
+	/// If the option was given a number, the last number given is
+	/// returned, otherwise the number of times it was given.  The
+	/// level is clamped to @p max and is zero if the option was not
+	/// set or was negated.  Values which are not numbers are ignored
+	pub fn level_value(&self, flags : &ParseResult, opt: &Opt, max: uint) -> uint {
+		let matches = self.matches_named(flags, opt.long_parsed());
+		let explicit = matches.iter().filter_map(|opt_match| {
+			opt_match.val.as_ref().and_then(|val| from_str::<uint>(*val))
+		}).last();
+		let level = match explicit {
+			Some(level) => level,
+			None => matches.len()
+		};
+		if level > max { max } else { level }
+	}
+
+	/// Returns the value for a given option parsed as a floating-point
+	/// number, an error if the value is not a number or None if the
+	/// option was not set
+	pub fn float_value(&self, flags : &ParseResult, opt: &Opt) -> Option<Result<f64, ~str>> {
+		self.value(flags, opt).map(|val| {
+			match from_str::<f64>(val) {
+				Some(num) => Ok(num),
+				None => Err(fill(self.messages.invalid_number, [("option", opt.long_parsed()), ("value", val)]))
+			}
+		})
+	}
+
+	/// Returns the value for a given option parsed as a size in bytes,
+	/// an error if the value is not a size or None if the option was
+	/// not set.  The value is a number optionally followed by one of
+	/// the suffixes 'K', 'M' or 'G', eg. '512', '10M' or '1.5G'.  The
+	/// suffixes are powers of 1024 unless decimal_sizes is set
+	pub fn size_value(&self, flags : &ParseResult, opt: &Opt) -> Option<Result<u64, ~str>> {
+		let base : u64 = if self.decimal_sizes { 1000 } else { 1024 };
+		let suffixes = [('K', base), ('M', base * base), ('G', base * base * base)];
+		self.value(flags, opt).map(|val| {
+			match parse_with_suffix(val, suffixes) {
+				Some(size) => Ok(size),
+				None => Err(fill(self.messages.invalid_size, [("option", opt.long_parsed()), ("value", val)]))
+			}
+		})
+	}
+
+	/// Returns the value for a given option parsed as a duration in
+	/// seconds, an error if the value is not a duration or None if the
+	/// option was not set.  The value is a number optionally followed
+	/// by one of the suffixes 's', 'm' or 'h' for seconds, minutes
+	/// or hours, eg. '30', '5m' or '1.5h'
+	pub fn duration_value(&self, flags : &ParseResult, opt: &Opt) -> Option<Result<u64, ~str>> {
+		let suffixes = [('s', 1), ('m', 60), ('h', 60 * 60)];
+		self.value(flags, opt).map(|val| {
+			match parse_with_suffix(val, suffixes) {
+				Some(duration) => Ok(duration),
+				None => Err(fill(self.messages.invalid_duration, [("option", opt.long_parsed()), ("value", val)]))
+			}
+		})
+	}
+
+	/// Returns whether a given option was given and with what value,
+	/// based on its last occurrence.  This distinguishes an option
+	/// with an optional argument which was given without a value
+	/// (eg. '--color') from one that was not given at all.  Unlike
+	/// value(), the option's default value is not used
+	pub fn optional_value(&self, flags : &ParseResult, opt: &Opt) -> OptionState {
+		match self.matches_named(flags, opt.long_parsed()).last() {
+			Some(opt_match) => match opt_match.val {
+				Some(ref val) => PresentWith(val.clone()),
+				None => Present
+			},
+			None => Absent
+		}
+	}
+
+	/// Returns the items in the values of a list-valued option, an
+	/// error if a value is malformed or None if the option was not
+	/// set.  Items are separated by commas or whitespace and may be
+	/// quoted with '"' to include separators, eg. '--paths "a b",c'
+	/// gives the items 'a b' and 'c'.  Within an item, a backslash
+	/// escapes a quote or a backslash.  If the option was given more
+	/// than once, the items from all of its values are returned
+	pub fn list_values(&self, flags : &ParseResult, opt: &Opt) -> Option<Result<Vec<~str>, ~str>> {
+		let values = self.values_named(flags, opt.long_parsed());
+		if values.len() == 0 {
+			return None
+		}
+		let mut items = Vec::new();
+		for val in values.iter() {
+			match split_list(*val) {
+				Some(val_items) => items.push_all_move(val_items),
+				None => return Some(Err(fill(self.messages.unbalanced_quotes,
+				  [("option", opt.long_parsed()), ("value", *val)])))
+			}
+		}
+		Some(Ok(items))
+	}
+
+	/// Returns all of the values for a given option.  If the
+	/// option is negatable, only the values given after it was
+	/// last negated are returned
+	pub fn values<'r>(&self, flags : &'r ParseResult, match_opt: &Opt) -> ~[&'r str] {
+		self.values_iter(flags, match_opt).collect()
+	}
+
+	/// Returns an iterator over the values for a given option, in
+	/// the same way as values() but without collecting them first
+	pub fn values_iter<'r>(&self, flags : &'r ParseResult, opt: &Opt) -> ValuesIter<'r> {
+		// values given before the last negation are skipped
+		let mut start = 0;
+		for (index, opt_match) in flags.opts.iter().enumerate() {
+			let match_name : &str = opt_match.opt_name;
+			if opt_match.negated && match_name == opt.long_parsed() {
+				start = index + 1;
+			}
+		}
+		ValuesIter {
+			matches : flags.opts.slice_from(start).iter(),
+			name : opt.long_parsed().to_owned()
+		}
+	}
+
+	// returns the values for the option with a given long name,
+	// discarding any given before a negation of the option
+	fn values_named<'r>(&self, flags : &'r ParseResult, name : &str) -> Vec<&'r str> {
+		self.matches_named(flags, name).iter().filter_map(|opt_match| {
+			opt_match.val.as_ref().map(|val| val.as_slice())
+		}).collect()
+	}
+
+	// returns the matches for the option with a given long name,
+	// discarding any before a negation of the option
+	fn matches_named<'r>(&self, flags : &'r ParseResult, name : &str) -> Vec<&'r OptMatch> {
+		let mut matches = Vec::new();
+		for opt_match in flags.opts.iter() {
+			let match_name : &str = opt_match.opt_name;
+			if match_name == name {
+				if opt_match.negated {
+					matches.clear();
+				} else {
+					matches.push(opt_match);
+				}
+			}
+		}
+		matches
+	}
+
+	/// Returns Some(true) if a given option was set, Some(false) if
+	/// it was negated (eg. with '--no-option') after it was last set
+	/// or None if it was not passed on the command-line
+	pub fn bool_value(&self, flags : &ParseResult, opt: &Opt) -> Option<bool> {
+		flags.opts.iter().filter(|opt_match| {
+			let match_name : &str = opt_match.opt_name;
+			match_name == opt.long_parsed()
+		}).last().map(|opt_match| !opt_match.negated)
+	}
+
+	/// Returns the spelling used on the command-line for the last
+	/// occurrence of a given option, eg. '-o' if the short form of
+	/// '--option' was used, or None if the option was not passed
+	pub fn matched_as(&self, flags : &ParseResult, opt: &Opt) -> Option<~str> {
+		flags.opts.iter().filter(|opt_match| {
+			let match_name : &str = opt_match.opt_name;
+			match_name == opt.long_parsed()
+		}).last().map(|opt_match| opt_match.raw.clone())
+	}
+
+	/// Returns the value for the option with a given long name
+	/// if set or None otherwise, in the same way as value().
+	/// The name may be given with or without the leading dashes,
+	/// eg. '--option' or 'option'.
+	pub fn value_by_name<'r>(&'r self, flags : &'r ParseResult, long: &str) -> Option<&'r str> {
+		let name = long_name(long);
+		match self.opts.iter().find(|opt| opt.long_parsed() == name.as_slice()) {
+			Some(opt) => self.value(flags, *opt),
+			None => self.values_named(flags, name.as_slice()).last().map(|val| *val)
+		}
+	}
+
+	/// Returns true if the option with a given long name was
+	/// passed on the command-line.  See value_by_name()
+	pub fn is_set_by_name(&self, flags : &ParseResult, long: &str) -> bool {
+		self.matches_named(flags, long_name(long).as_slice()).len() > 0
+	}
+
+	/// Returns the parser's options which do not appear in a parse
+	/// result, in the order they were added, eg. for applying defaults
+	/// to everything the user did not specify.  The built-in --help
+	/// and --version options are not included.
+	pub fn unset_options(&self, flags : &ParseResult) -> Vec<&'a Opt> {
+		self.opts.iter().filter(|opt| {
+			!flags.opts.iter().any(|opt_match| {
+				let match_name : &str = opt_match.opt_name;
+				match_name == opt.long_parsed()
+			})
+		}).map(|opt| *opt).collect()
+	}
+
+	/// Returns a map from the long name of each option which was
+	/// set to its value.  If an option was given more than once,
+	/// the map holds its last value, as returned by value().
+	/// Use values() to get all of the values of a repeated option.
+	/// Options given without a value map to an empty string.
+	pub fn as_map<'r>(&self, flags : &'r ParseResult) -> HashMap<~str, &'r str> {
+		let mut map = HashMap::new();
+		for opt_match in flags.opts.iter() {
+			if opt_match.negated {
+				map.remove(&opt_match.opt_name);
+			} else {
+				let val : &'r str = match opt_match.val {
+					Some(ref val) => val.as_slice(),
+					None => ""
+				};
+				map.insert(opt_match.opt_name.clone(), val);
+			}
+		}
+		map
+	}
+
+	/// Returns a canonical command line for a parse result, eg. for
+	/// logging how a command was run.  This consists of the program
+	/// name, each option in its long form (eg. '--option=value' or
+	/// '--no-option'), once per occurrence, then the non-option
+	/// arguments.  An option which consumes the rest of the arguments
+	/// is given once, last, followed by its values, eg.
+	/// '--exec ls -la /tmp'.  Arguments containing spaces or other
+	/// characters which are special to the shell are quoted.  The
+	/// terminator (normally '--') is inserted before the non-option
+	/// arguments if any of them start with '-'
+	pub fn normalized_command_line(&self, flags : &ParseResult) -> ~str {
+		let mut words = vec!(shell_quote(self.program()));
+		// a consumes_rest option takes all of the arguments which
+		// follow it, so it is given once, last, followed by its values
+		let mut rest_words = Vec::new();
+		for opt_match in flags.opts.iter() {
+			if self.consumes_rest(opt_match.opt_name) && !opt_match.negated {
+				if rest_words.is_empty() {
+					rest_words.push(opt_match.opt_name.clone());
+				}
+				for val in opt_match.val.iter() {
+					rest_words.push(shell_quote(*val));
+				}
+			} else {
+				words.push(shell_quote(format!("{}", *opt_match)));
+			}
+		}
+		if rest_words.is_empty() && flags.args.iter().any(|arg| arg.starts_with("-")) {
+			words.push(self.terminator.clone());
+		}
+		for arg in flags.args.iter() {
+			words.push(shell_quote(*arg));
+		}
+		words.push_all_move(rest_words);
+		words.connect(" ")
+	}
+
+	// returns true if the option with a given long name takes all
+	// of the arguments which follow it as its values
+	fn consumes_rest(&self, name: &str) -> bool {
+		self.opts.iter().any(|opt| opt.consumes_rest && opt.long_parsed() == name)
+	}
+
+	/// Returns a JSON object describing the options, positional
+	/// arguments and status of a parse result, eg.
+	/// '{"options": {"--opt": "val"}, "args": ["a"], "status": "Success"}'.
+	///
+	/// Options which were given more than once are rendered as
+	/// an array of their values.  An option given without a value
+	/// has the value null, so that eg. '--name' and '--name=' differ.
+	pub fn to_json(&self, flags : &ParseResult) -> ~str {
+		let mut names : Vec<&str> = Vec::new();
 		for opt_match in flags.opts.iter() {
 			let name : &str = opt_match.opt_name;
-			if name == match_opt.long_parsed() {
-				let val : &'r str = opt_match.val;
-				matches.push(val);
+			if !names.contains(&name) {
+				names.push(name);
 			}
 		}
-		matches.as_slice().to_owned()
+
+		let mut options : Vec<~str> = Vec::new();
+		for name in names.iter() {
+			let vals : Vec<~str> = self.matches_named(flags, *name).iter().map(|opt_match| {
+				match opt_match.val {
+					Some(ref val) => json_str(*val),
+					None => "null".to_owned()
+				}
+			}).collect();
+			if vals.len() == 0 {
+				// the option was negated
+				continue
+			}
+			let val = if vals.len() == 1 {
+				vals.get(0).clone()
+			} else {
+				format!("[{}]", vals.connect(", "))
+			};
+			options.push(format!("{}: {}", json_str(*name), val));
+		}
+
+		let args : Vec<~str> = flags.args.iter().map(|arg| json_str(*arg)).collect();
+
+		let status = format!("{}", flags.status);
+
+		let mut json = StrBuf::new();
+		json.push_str("{\"options\": {");
+		json.push_str(options.connect(", "));
+		json.push_str("}, \"args\": [");
+		json.push_str(args.connect(", "));
+		json.push_str("], \"status\": ");
+		json.push_str(json_str(status));
+		json.push_char('}');
+		json.into_owned()
 	}
 
-	/// Returns true if a given flag was passed on the command-line
+	/// Returns true if a given flag was passed on the command-line,
+	/// either on its own or as part of a group of short options
+	/// (eg. '-abc').  This does not depend on the option's value,
+	/// so it is true both for options given without a value and
+	/// for options given an empty value
 	pub fn is_set(&self, flags : &ParseResult, opt: &Opt) -> bool {
-		match self.value(flags, opt) {
-			Some(_) => true,
-			None => false
+		self.matches_named(flags, opt.long_parsed()).len() > 0
+	}
+
+	/// Returns the number of non-option arguments
+	pub fn arg_count(&self, flags : &ParseResult) -> uint {
+		flags.args.len()
+	}
+
+	/// Returns the non-option argument at a given position
+	/// or None if there are not that many arguments
+	pub fn positional<'r>(&self, flags : &'r ParseResult, index : uint) -> Option<&'r str> {
+		flags.args.as_slice().get(index).map(|arg| arg.as_slice())
+	}
+
+	/// Returns true if the automatically added help option was
+	/// used, regardless of whether usage information was printed
+	pub fn wants_help(&self, flags : &ParseResult) -> bool {
+		self.auto_help && self.is_set(flags, &self.builtin_help_opt())
+	}
+
+	/// Returns true if the automatically added version option was
+	/// used, regardless of whether the version was printed.
+	/// See enable_version()
+	pub fn wants_version(&self, flags : &ParseResult) -> bool {
+		self.auto_version && self.is_set_by_name(flags, "--version")
+	}
+
+	/// Returns the options and non-option arguments from a parse
+	/// result in the order in which they were given on the
+	/// command-line, eg. for commands where the meaning of an
+	/// argument depends on the options before it.  Options read
+	/// from a config file by parse_with_config() are not included
+	pub fn tokens(&self, flags : &ParseResult) -> Vec<Token> {
+		flags.tokens.clone()
+	}
+
+	/// Returns the first non-option argument, for commands whose first
+	/// argument is a command word, eg. 'build' for 'mytool build --release'.
+	/// When parsing with parse(), the program name is skipped, so this
+	/// is the first non-option argument after it.  With parse_iter(),
+	/// it is the first non-option argument in the sequence.
+	pub fn command<'r>(&self, flags : &'r ParseResult) -> Option<&'r str> {
+		self.positional(flags, 0)
+	}
+
+	/// Returns the non-option arguments following the command word
+	/// returned by command()
+	pub fn command_args<'r>(&self, flags : &'r ParseResult) -> &'r [~str] {
+		if flags.args.len() > 0 {
+			flags.args.slice_from(1)
+		} else {
+			&[]
+		}
+	}
+
+	/// Returns the arguments collected by the trailing variadic
+	/// argument called @p name, or an empty slice if the parser
+	/// does not have one with that name.  See set_variadic()
+	pub fn variadic<'r>(&self, flags : &'r ParseResult, name : &str) -> &'r [~str] {
+		match self.variadic_name {
+			Some(ref variadic_name) if variadic_name.as_slice() == name => flags.args.as_slice(),
+			_ => &[]
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::cell::Cell;
+	use std::io::File;
+	use std::io::fs;
+	use std::os;
+
+	use super::{Opt, OptionParser, ParseResult, Token, OptionToken, PositionalToken, Absent,
+	            Present, PresentWith, IntArg, PathExists, PathIsFile, PathIsDir, PassThroughUnknown,
+	            CollectUnknown};
+	use testing::{assert_set, assert_not_set, assert_value, assert_args, assert_error};
+
+	// returns a command line for parse(), with the program name
+	// followed by @p list
+	fn args(list: &[&str]) -> ~[~str] {
+		let mut args = vec!("prog".to_owned());
+		for arg in list.iter() {
+			args.push((*arg).to_owned());
+		}
+		args.as_slice().to_owned()
+	}
+
+	// returns a parser which does not print anything and has a
+	// fixed program name, so that the output does not depend on
+	// how the tests are run
+	fn test_parser<'a>(opts: &[&'a Opt]) -> OptionParser<'a> {
+		let mut parser = OptionParser::new("[options]", "A test program", opts);
+		parser.quiet = true;
+		parser.program_name = Some("prog".to_owned());
+		parser
+	}
+
+	fn status(result: &ParseResult) -> ~str {
+		format!("{}", result.status)
+	}
+
+	fn assert_strs<S: Str>(actual: &[S], expected: &[&str]) {
+		let actual : Vec<&str> = actual.iter().map(|s| s.as_slice()).collect();
+		assert!(actual.as_slice() == expected, "Expected [{}] but got [{}]", expected.connect(", "), actual.connect(", "));
+	}
+
+	fn ok_value<T>(value: Option<Result<T, ~str>>) -> T {
+		match value {
+			Some(Ok(value)) => value,
+			Some(Err(error)) => fail!("Expected a valid value but got the error '{}'", error),
+			None => fail!("Expected a valid value but the option was not set")
+		}
+	}
+
+	fn is_err_value<T>(value: Option<Result<T, ~str>>) -> bool {
+		match value {
+			Some(Err(_)) => true,
+			_ => false
+		}
+	}
+
+	fn token_str(token: &Token) -> ~str {
+		match *token {
+			OptionToken(ref name, Some(ref val)) => format!("{}={}", *name, *val),
+			OptionToken(ref name, None) => name.clone(),
+			PositionalToken(ref arg) => arg.clone()
+		}
+	}
+
+	fn first_error(result: &ParseResult) -> ~str {
+		match result.first_error() {
+			Some(error) => error.to_owned(),
+			None => fail!("Expected an error in {}", *result)
+		}
+	}
+
+	// parsing
+
+	#[test]
+	fn options_and_arguments_via_testing_helpers() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["-v", "--output=out.txt", "in.txt"]));
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "out.txt");
+		assert_args(&result, ["in.txt"]);
+	}
+
+	#[test]
+	fn unknown_option_via_testing_helpers() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["--bogus", "in.txt"]));
+		assert_error(&result);
+		assert_not_set(&parser, &result, &verbose);
+		assert_args(&result, ["in.txt"]);
+	}
+
+	#[test]
+	fn parse_iter_keeps_the_first_argument() {
+		let parser = test_parser([]);
+		let result = parser.parse_iter(vec!("file.txt".to_owned()).move_iter());
+		assert_args(&result, ["file.txt"]);
+	}
+
+	#[test]
+	fn only_the_first_terminator_ends_options() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["--", "--"]));
+		assert_args(&result, ["--"]);
+
+		let result = parser.parse(args(["--", "-v"]));
+		assert_not_set(&parser, &result, &verbose);
+		assert_args(&result, ["-v"]);
+	}
+
+	#[test]
+	fn custom_terminator_replaces_double_dash() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose]);
+		parser.terminator = "++".to_owned();
+		let result = parser.parse(args(["++", "-v"]));
+		assert_not_set(&parser, &result, &verbose);
+		assert_args(&result, ["-v"]);
+
+		let result = parser.parse(args(["--", "-v"]));
+		assert_set(&parser, &result, &verbose);
+		assert_args(&result, ["--"]);
+	}
+
+	#[test]
+	fn lone_dash_is_positional() {
+		let parser = test_parser([]);
+		let result = parser.parse(args(["-"]));
+		assert_args(&result, ["-"]);
+	}
+
+	#[test]
+	#[should_fail]
+	fn short_option_without_dash_fails() {
+		Opt::new("o", "--output", "Output file");
+	}
+
+	#[test]
+	#[should_fail]
+	fn short_option_with_several_letters_fails() {
+		Opt::new("-ab", "--output", "Output file");
+	}
+
+	#[test]
+	#[should_fail]
+	fn short_option_with_double_dash_fails() {
+		Opt::new("--o", "--output", "Output file");
+	}
+
+	#[test]
+	#[should_fail]
+	fn long_option_without_dashes_fails() {
+		Opt::new("-o", "output", "Output file");
+	}
+
+	#[test]
+	#[should_fail]
+	fn unclosed_optional_argument_fails() {
+		Opt::new("", "--opt [ARG", "An option");
+	}
+
+	#[test]
+	#[should_fail]
+	fn unopened_optional_argument_fails() {
+		Opt::new("", "--opt ARG]", "An option");
+	}
+
+	#[test]
+	fn well_formed_arguments() {
+		let required = Opt::new("", "--opt ARG", "An option");
+		assert!(required.has_required_arg());
+		let optional = Opt::new("", "--opt [ARG]", "An option");
+		assert!(optional.has_arg());
+		assert!(!optional.has_required_arg());
+	}
+
+	#[test]
+	fn disabled_auto_help_treats_help_as_unknown() {
+		let mut parser = test_parser([]);
+		parser.auto_help = false;
+		let result = parser.parse(args(["--help"]));
+		assert_error(&result);
+		assert!(!parser.wants_help(&result));
+		assert_eq!(status(&result), "Error".to_owned());
+	}
+
+	#[test]
+	fn later_negation_wins() {
+		let mut feature = Opt::new("", "--feature", "Enable the feature");
+		feature.negatable = true;
+		let parser = test_parser([&feature]);
+
+		let result = parser.parse(args(["--feature", "--no-feature"]));
+		assert_not_set(&parser, &result, &feature);
+		assert!(parser.bool_value(&result, &feature) == Some(false));
+
+		let result = parser.parse(args(["--no-feature", "--feature"]));
+		assert_set(&parser, &result, &feature);
+		assert!(parser.bool_value(&result, &feature) == Some(true));
+	}
+
+	#[test]
+	fn negation_does_not_take_an_argument() {
+		let mut feature = Opt::new("", "--feature", "Enable the feature");
+		feature.negatable = true;
+		let parser = test_parser([&feature]);
+		let result = parser.parse(args(["--feature", "--no-feature=1"]));
+		assert!(first_error(&result).starts_with("Option --no-feature does not take an argument"));
+		assert_set(&parser, &result, &feature);
+	}
+
+	#[test]
+	fn negation_is_not_an_occurrence() {
+		let mut color = Opt::new("", "--color", "Colorize output");
+		color.negatable = true;
+		color.max_occurrences = Some(1);
+		let parser = test_parser([&color]);
+		let result = parser.parse(args(["--color", "--no-color"]));
+		assert!(result.errors.is_empty());
+	}
+
+	#[test]
+	fn grouped_short_flags() {
+		let a = Opt::new("-a", "--all", "All");
+		let b = Opt::new("-b", "--brief", "Brief");
+		let c = Opt::new("-c", "--count", "Count");
+		let parser = test_parser([&a, &b, &c]);
+		let result = parser.parse(args(["-abc"]));
+		assert_set(&parser, &result, &a);
+		assert_set(&parser, &result, &b);
+		assert_set(&parser, &result, &c);
+		assert_args(&result, []);
+	}
+
+	#[test]
+	fn repeated_letter_in_group_counts_twice() {
+		let a = Opt::new("-a", "--all", "All");
+		let parser = test_parser([&a]);
+		let result = parser.parse(args(["-aa"]));
+		assert_set(&parser, &result, &a);
+		assert_eq!(result.opts.len(), 2);
+		assert_eq!(parser.level_value(&result, &a, 10), 2);
+	}
+
+	#[test]
+	fn numeric_value_attached_to_short_option() {
+		let jobs = Opt::new("-j", "--jobs N", "Number of jobs");
+		let parser = test_parser([&jobs]);
+		let attached = parser.parse(args(["-j4"]));
+		let separate = parser.parse(args(["-j", "4"]));
+		assert_value(&parser, &attached, &jobs, "4");
+		assert_value(&parser, &separate, &jobs, "4");
+		assert_eq!(parser.normalized_command_line(&attached), parser.normalized_command_line(&separate));
+	}
+
+	#[test]
+	fn abbreviated_long_options() {
+		let output = Opt::new("", "--output FILE", "Output file");
+		let other = Opt::new("", "--other", "Another option");
+		let parser = test_parser([&output, &other]);
+		let result = parser.parse(args(["--out=x"]));
+		assert_value(&parser, &result, &output, "x");
+
+		let result = parser.parse(args(["--o"]));
+		assert_eq!(first_error(&result), "Option --o is ambiguous, it could be: --output, --other".to_owned());
+
+		let result = parser.parse(args(["--=foo"]));
+		assert_error(&result);
+	}
+
+	#[test]
+	fn escaped_dash_in_value() {
+		let message = Opt::new("-m", "--message MSG", "Commit message");
+		let parser = test_parser([&message]);
+		let result = parser.parse(args(["--message", "\\-x"]));
+		assert_value(&parser, &result, &message, "-x");
+		let result = parser.parse(args(["--message=-x"]));
+		assert_value(&parser, &result, &message, "-x");
+	}
+
+	#[test]
+	fn invalid_choice_suggestion() {
+		let mut format = Opt::new("", "--format FORMAT", "Output format");
+		format.choices = vec!("json".to_owned(), "xml".to_owned());
+		let parser = test_parser([&format]);
+		let result = parser.parse(args(["--format", "jsom"]));
+		assert_eq!(first_error(&result), "Invalid value 'jsom' for option --format, did you mean 'json'?".to_owned());
+		let result = parser.parse(args(["--format", "plaintext"]));
+		assert_eq!(first_error(&result),
+		  "Invalid value 'plaintext' for option --format, expected one of: json, xml".to_owned());
+		let result = parser.parse(args(["--format", "xml"]));
+		assert_value(&parser, &result, &format, "xml");
+	}
+
+	#[test]
+	fn unknown_option_policies() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose]);
+		parser.unknown_short = PassThroughUnknown;
+		parser.unknown_long = PassThroughUnknown;
+		let result = parser.parse(args(["-x", "--child=1", "file"]));
+		assert_args(&result, ["-x", "--child=1", "file"]);
+		assert!(result.errors.is_empty());
+
+		parser.unknown_short = CollectUnknown;
+		parser.unknown_long = CollectUnknown;
+		let result = parser.parse(args(["-x", "--child=1", "file"]));
+		assert_args(&result, ["file"]);
+		assert_strs(result.unknown.as_slice(), ["-x", "--child=1"]);
+
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["-x", "--child=1", "file"]));
+		assert_eq!(result.error_count(), 2);
+		assert_args(&result, ["file"]);
+	}
+
+	#[test]
+	fn stop_at_unknown_keeps_the_rest() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose]);
+		parser.stop_at_unknown = true;
+		let result = parser.parse(args(["run", "--verbose", "child", "--child-only-flag"]));
+		assert_set(&parser, &result, &verbose);
+		assert_args(&result, ["run", "child", "--child-only-flag"]);
+	}
+
+	#[test]
+	fn empty_values() {
+		let name = Opt::new("", "--name NAME", "Name");
+		let color = Opt::new("", "--color [WHEN]", "Colorize output");
+		let parser = test_parser([&name, &color]);
+		let result = parser.parse(args(["--name", ""]));
+		assert_set(&parser, &result, &name);
+		assert_value(&parser, &result, &name, "");
+
+		let result = parser.parse(args(["--name=", "--color"]));
+		assert_value(&parser, &result, &name, "");
+		assert_set(&parser, &result, &color);
+		assert!(parser.value(&result, &color).is_none());
+
+		let json = parser.to_json(&result);
+		assert!(json.contains("\"--name\": \"\""), "{}", json);
+		assert!(json.contains("\"--color\": null"), "{}", json);
+	}
+
+	#[test]
+	fn variadic_arguments() {
+		let mut parser = test_parser([]);
+		parser.set_variadic("FILES", 0);
+		let result = parser.parse(args([]));
+		assert_eq!(parser.variadic(&result, "FILES").len(), 0);
+		let result = parser.parse(args(["a"]));
+		assert_strs(parser.variadic(&result, "FILES"), ["a"]);
+		let result = parser.parse(args(["a", "b", "c"]));
+		assert_strs(parser.variadic(&result, "FILES"), ["a", "b", "c"]);
+		assert_eq!(parser.usage_line(), "Usage: prog [options] FILES...".to_owned());
+
+		parser.set_variadic("FILES", 1);
+		let result = parser.parse(args([]));
+		assert_eq!(first_error(&result), "At least 1 FILES arguments are required (given 0)".to_owned());
+	}
+
+	#[test]
+	fn tokens_keep_command_line_order() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["-v", "a", "--output", "x", "b"]));
+		let tokens : Vec<~str> = parser.tokens(&result).iter().map(token_str).collect();
+		assert_strs(tokens.as_slice(), ["--verbose", "a", "--output=x", "b"]);
+	}
+
+	#[test]
+	fn consumes_rest() {
+		let mut exec = Opt::new("", "--exec CMD", "Command to run");
+		exec.consumes_rest = true;
+		exec.max_occurrences = Some(1);
+		let mut command = None;
+		{
+			let mut parser = test_parser([&exec]);
+			let result = parser.parse(args(["--exec", "ls", "-la", "/tmp"]));
+			assert!(result.errors.is_empty());
+			assert_strs(parser.values(&result, &exec).as_slice(), ["ls", "-la", "/tmp"]);
+			assert_eq!(parser.normalized_command_line(&result), "prog --exec ls -la /tmp".to_owned());
+
+			parser.set_handler(&exec, |val| command = val.map(|val| val.to_owned()));
+			assert!(parser.run(args(["--exec", "ls", "-la", "/tmp"])).is_some());
+		}
+		assert!(command == Some("ls -la /tmp".to_owned()));
+	}
+
+	#[test]
+	fn consumed_arguments_are_kept_as_given() {
+		let mut exec = Opt::new("", "--exec CMD", "Command to run");
+		exec.consumes_rest = true;
+		exec.trim = true;
+		let parser = test_parser([&exec]);
+		let result = parser.parse(args(["--exec", "printf", "\\-n", " x "]));
+		assert!(result.errors.is_empty());
+		assert_strs(parser.values(&result, &exec).as_slice(), ["printf", "\\-n", " x "]);
+	}
+
+	#[test]
+	fn consuming_option_given_last() {
+		let mut exec = Opt::new("", "--exec CMD", "Command to run");
+		exec.consumes_rest = true;
+		let parser = test_parser([&exec]);
+		let result = parser.parse(args(["--exec"]));
+		assert!(first_error(&result).starts_with("Option --exec requires an argument but none was given."));
+
+		let mut shell = Opt::new("", "--shell [CMD]", "Run a shell, or a command in it");
+		shell.consumes_rest = true;
+		let parser = test_parser([&shell]);
+		let result = parser.parse(args(["--shell"]));
+		assert!(result.errors.is_empty());
+		assert!(parser.is_set(&result, &shell));
+		assert!(parser.values(&result, &shell).is_empty());
+	}
+
+	#[test]
+	fn trailing_target_takes_values_after_terminator() {
+		let command = Opt::new("", "--command CMD", "Command to run");
+		let mut parser = test_parser([&command]);
+		parser.set_trailing_target(&command);
+		let result = parser.parse(args(["run", "--", "child", "--child-flag", "\\-x"]));
+		assert_args(&result, ["run"]);
+		assert_strs(parser.values(&result, &command).as_slice(), ["child", "--child-flag", "\\-x"]);
+	}
+
+	#[test]
+	fn positionals_only() {
+		let parser = test_parser([]);
+		let positionals = parser.positionals_only(args(["-v", "a", "--", "-b"]));
+		assert_strs(positionals.as_slice(), ["a", "-b"]);
+
+		let mut parser = test_parser([]);
+		parser.unknown_long = PassThroughUnknown;
+		let positionals = parser.positionals_only(args(["--child", "a"]));
+		assert_strs(positionals.as_slice(), ["--child", "a"]);
+	}
+
+	#[test]
+	fn argument_indices() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["-v", "a", "--output", "x", "b"]));
+		assert!(result.opts.get(0).index() == Some(1));
+		assert!(result.opts.get(1).index() == Some(3));
+	}
+
+	#[test]
+	fn abbreviated_subcommands() {
+		let mut parser = test_parser([]);
+		parser.subcommands = vec!("status".to_owned(), "stash".to_owned(), "commit".to_owned());
+		parser.abbreviate_subcommands = true;
+		let result = parser.parse(args(["co"]));
+		assert_args(&result, ["commit"]);
+		let result = parser.parse(args(["st"]));
+		assert_eq!(first_error(&result), "Command st is ambiguous, it could be: status, stash".to_owned());
+		let result = parser.parse(args(["stash"]));
+		assert!(result.errors.is_empty());
+		assert_args(&result, ["stash"]);
+	}
+
+	#[test]
+	fn normalized_command_line_round_trips() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["-v", "a", "-o", "out file", "b"]));
+		let normalized = parser.normalized_command_line(&result);
+		assert_eq!(normalized, "prog --verbose '--output=out file' a b".to_owned());
+
+		let reparsed = parser.parse(args(["--verbose", "--output=out file", "a", "b"]));
+		assert_eq!(parser.normalized_command_line(&reparsed), normalized);
+	}
+
+	#[test]
+	fn optional_value_states() {
+		let color = Opt::new("", "--color [WHEN]", "Colorize output");
+		let parser = test_parser([&color]);
+		let result = parser.parse(args([]));
+		assert!(match parser.optional_value(&result, &color) { Absent => true, _ => false });
+		let result = parser.parse(args(["--color"]));
+		assert!(match parser.optional_value(&result, &color) { Present => true, _ => false });
+		let result = parser.parse(args(["--color=always"]));
+		assert!(match parser.optional_value(&result, &color) {
+			PresentWith(ref val) => val.as_slice() == "always",
+			_ => false
+		});
+	}
+
+	#[test]
+	fn trimmed_values() {
+		let mut trimmed = Opt::new("", "--name NAME", "Name");
+		trimmed.trim = true;
+		let untrimmed = Opt::new("", "--title TITLE", "Title");
+		let parser = test_parser([&trimmed, &untrimmed]);
+		let result = parser.parse(args(["--name", " x ", "--title", " y "]));
+		assert_value(&parser, &result, &trimmed, "x");
+		assert_value(&parser, &result, &untrimmed, " y ");
+
+		// values in a config file are always trimmed
+		let path = os::tmpdir().join("optparse-test-trimmed-values.conf");
+		File::create(&path).write_str("name =  x \ntitle =  y \n").unwrap();
+		let result = parser.parse_with_config(args([]), &path);
+		let _ = fs::unlink(&path);
+		assert_value(&parser, &result, &trimmed, "x");
+		assert_value(&parser, &result, &untrimmed, "y");
+	}
+
+	#[test]
+	fn config_file_errors() {
+		let name = Opt::new("", "--name NAME", "Name");
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&name, &verbose]);
+		let path = os::tmpdir().join("optparse-test-config-file-errors.conf");
+		File::create(&path).write_str("# a comment\n\nname = from config\nverbose = true\n\
+		                               not a setting\ncolour = red\nverbose = maybe\n").unwrap();
+		let from_config = parser.parse_with_config(args([]), &path);
+		let from_command_line = parser.parse_with_config(args(["--name", "given"]), &path);
+		let _ = fs::unlink(&path);
+
+		let location = format!("{}", path.display());
+		assert_eq!(status(&from_config), "Error".to_owned());
+		assert_value(&parser, &from_config, &name, "from config");
+		assert_set(&parser, &from_config, &verbose);
+		assert_strs(from_config.errors.as_slice(), [
+		  format!("{}:5: expected 'name = value', found 'not a setting'", location).as_slice(),
+		  format!("{}:6: unknown option --colour", location).as_slice(),
+		  format!("{}:7: invalid value 'maybe' for option --verbose, expected 'true' or 'false'", location).as_slice()]);
+		assert_value(&parser, &from_command_line, &name, "given");
+	}
+
+	#[test]
+	fn command_word() {
+		let release = Opt::new("", "--release", "Build with optimizations");
+		let parser = test_parser([&release]);
+		let result = parser.parse(args(["build", "--release"]));
+		assert!(parser.command(&result) == Some("build"));
+		assert_strs(parser.command_args(&result), []);
+	}
+
+	#[test]
+	fn options_before_positionals() {
+		let foo = Opt::new("", "--foo", "Foo");
+		let mut parser = test_parser([&foo]);
+		let result = parser.parse(args(["file.txt", "--foo"]));
+		assert!(result.errors.is_empty());
+
+		parser.options_before_positionals = true;
+		let result = parser.parse(args(["file.txt", "--foo"]));
+		assert_eq!(first_error(&result), "Options must come before arguments: --foo appeared after 'file.txt'".to_owned());
+	}
+
+	#[test]
+	fn help_after_positionals() {
+		let foo = Opt::new("", "--foo", "Foo");
+		let mut parser = test_parser([&foo]);
+		parser.options_before_positionals = true;
+		parser.enable_version("1.2.3");
+		let result = parser.parse(args(["file.txt", "--help"]));
+		assert!(result.errors.is_empty());
+		assert_eq!(status(&result), "Help".to_owned());
+		let result = parser.parse(args(["file.txt", "--version"]));
+		assert_eq!(status(&result), "Version".to_owned());
+	}
+
+	#[test]
+	fn list_values() {
+		let paths = Opt::new("", "--paths LIST", "Paths");
+		let parser = test_parser([&paths]);
+		let result = parser.parse(args(["--paths", "\"a b\",c"]));
+		assert_strs(ok_value(parser.list_values(&result, &paths)).as_slice(), ["a b", "c"]);
+		let result = parser.parse(args(["--paths", "\"say \\\"hi\\\"\""]));
+		assert_strs(ok_value(parser.list_values(&result, &paths)).as_slice(), ["say \"hi\""]);
+		let result = parser.parse(args(["--paths", "a,b,"]));
+		assert_strs(ok_value(parser.list_values(&result, &paths)).as_slice(), ["a", "b"]);
+		let result = parser.parse(args(["--paths", "\"a,b"]));
+		assert!(is_err_value(parser.list_values(&result, &paths)));
+	}
+
+	#[test]
+	fn programmatic_default() {
+		let mode = Opt::new("", "--mode MODE", "Mode");
+		let mut parser = test_parser([&mode]);
+		parser.set_default(&mode, "fast");
+		let result = parser.parse(args([]));
+		assert_not_set(&parser, &result, &mode);
+		assert_value(&parser, &result, &mode, "fast");
+		let result = parser.parse(args(["--mode", "slow"]));
+		assert_value(&parser, &result, &mode, "slow");
+	}
+
+	#[test]
+	fn short_options_disabled() {
+		let a = Opt::new("-a", "--all", "All");
+		let mut parser = test_parser([&a]);
+		parser.short_options_enabled = false;
+		let result = parser.parse(args(["-abc"]));
+		assert_not_set(&parser, &result, &a);
+		assert_args(&result, ["-abc"]);
+	}
+
+	#[test]
+	fn value_error_fields() {
+		let count = Opt::new("", "--count N", "Count");
+		let parser = test_parser([&count]);
+		let result = parser.parse(args(["a", "--count", "x"]));
+		match parser.value_as::<int>(&result, &count) {
+			Some(Err(error)) => {
+				assert_eq!(error.option, "--count".to_owned());
+				assert_eq!(error.value, "x".to_owned());
+				assert!(error.index == Some(2));
+				assert_eq!(format!("{}", error), "Invalid value 'x' for option --count (argument 2)".to_owned());
+			}
+			_ => fail!("Expected an error for --count")
 		}
+		let result = parser.parse(args(["--count", "3"]));
+		assert!(match parser.value_as::<int>(&result, &count) { Some(Ok(3)) => true, _ => false });
+	}
+
+	#[test]
+	fn level_value() {
+		let verbose = Opt::new("-v", "--verbosity [LEVEL]", "Verbosity");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["-vv"]));
+		assert_eq!(parser.level_value(&result, &verbose, 5), 2);
+		let result = parser.parse(args(["--verbosity", "3"]));
+		assert_eq!(parser.level_value(&result, &verbose, 5), 3);
+		let result = parser.parse(args(["-v", "--verbosity", "3", "-v"]));
+		assert_eq!(parser.level_value(&result, &verbose, 5), 3);
+		let result = parser.parse(args(["-vvv"]));
+		assert_eq!(parser.level_value(&result, &verbose, 2), 2);
+	}
+
+	#[test]
+	fn preprocessor_expands_aliases() {
+		let quiet = Opt::new("-q", "--quiet", "Quiet output");
+		let parser = test_parser([&quiet]);
+		let result = parser.parse_with_preprocessor(args(["co"]), |args| {
+			let mut expanded = Vec::new();
+			for arg in args.iter() {
+				if arg.as_slice() == "co" {
+					expanded.push("checkout".to_owned());
+					expanded.push("--quiet".to_owned());
+				} else {
+					expanded.push(arg.clone());
+				}
+			}
+			expanded.as_slice().to_owned()
+		});
+		assert_set(&parser, &result, &quiet);
+		assert_args(&result, ["checkout"]);
+	}
+
+	#[test]
+	fn help_takes_precedence_over_errors() {
+		let parser = test_parser([]);
+		let result = parser.parse(args(["--help", "--bogus"]));
+		assert_eq!(status(&result), "Help".to_owned());
+		assert_eq!(result.error_count(), 1);
+		assert!(result.help_text().is_some());
+	}
+
+	#[test]
+	fn values_iter() {
+		let message = Opt::new("-m", "--message MSG", "Message");
+		let parser = test_parser([&message]);
+		let result = parser.parse(args(["-m", "a", "-m", "b"]));
+		let mut values = parser.values_iter(&result, &message);
+		assert!(values.next() == Some("a"));
+		assert!(values.next() == Some("b"));
+		assert!(values.next().is_none());
+	}
+
+	#[test]
+	fn value_separators() {
+		let opt = Opt::new("", "--opt VALUE", "An option");
+		let mut parser = test_parser([&opt]);
+		parser.value_separators = vec!('=', ':');
+		let result = parser.parse(args(["--opt:a=b"]));
+		assert_value(&parser, &result, &opt, "a=b");
+		let result = parser.parse(args(["--opt=c:d"]));
+		assert_value(&parser, &result, &opt, "c:d");
+	}
+
+	#[test]
+	fn missing_argument() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&output, &verbose]);
+		let result = parser.parse(args(["--output"]));
+		assert!(first_error(&result).starts_with("Option --output requires an argument but none was given."));
+		let result = parser.parse(args(["--output", "-v"]));
+		assert!(first_error(&result).starts_with("Option --output requires an argument but was followed by the option -v."));
+	}
+
+	#[test]
+	fn unexpected_argument() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["--verbose=1"]));
+		assert!(first_error(&result).starts_with("Option --verbose does not take an argument"));
+	}
+
+	#[test]
+	fn value_in_short_option_group() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["-vo=value"]));
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "value");
+
+		let result = parser.parse(args(["-voValue"]));
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "Value");
+
+		// letters after an option which requires an argument are its value
+		let result = parser.parse(args(["-ov"]));
+		assert_not_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "v");
+
+		// nothing after '=' is an empty value, as for '--output='
+		let result = parser.parse(args(["-vo="]));
+		assert!(result.errors.is_empty());
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "");
+		let result = parser.parse(args(["-o="]));
+		assert_value(&parser, &result, &output, "");
+	}
+
+	#[test]
+	fn value_or_else_is_lazy() {
+		let name = Opt::new("", "--name NAME", "Name");
+		let parser = test_parser([&name]);
+		let mut calls = 0;
+		let result = parser.parse(args(["--name", "given"]));
+		assert_eq!(parser.value_or_else(&result, &name, || { calls += 1; "default".to_owned() }), "given".to_owned());
+		assert_eq!(calls, 0);
+		let result = parser.parse(args([]));
+		assert_eq!(parser.value_or_else(&result, &name, || { calls += 1; "default".to_owned() }), "default".to_owned());
+		assert_eq!(calls, 1);
+	}
+
+	#[test]
+	fn error_handler_receives_each_error() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let mut errors = Vec::new();
+		let result = parser.parse_with_error_handler(args(["--bogus", "--verbose=1"]), |error| {
+			errors.push(error.to_owned())
+		});
+		assert_eq!(status(&result), "Error".to_owned());
+		assert_eq!(errors.len(), 2);
+		assert!(errors.get(0).starts_with("Unknown option --bogus"));
+		assert!(errors.get(1).starts_with("Option --verbose does not take an argument"));
+	}
+
+	#[test]
+	fn merge_keeps_the_most_severe_status() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let mut result = parser.parse(args(["-v", "-o", "first", "a"]));
+		result.merge(parser.parse(args(["-o", "second", "b"])));
+		assert_eq!(status(&result), "Success".to_owned());
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "second");
+		assert_args(&result, ["a", "b"]);
+
+		result.merge(parser.parse(args(["--help"])));
+		assert_eq!(status(&result), "Help".to_owned());
+		result.merge(parser.parse(args(["--bogus"])));
+		assert_eq!(status(&result), "Error".to_owned());
+		result.merge(parser.parse(args(["--help"])));
+		assert_eq!(status(&result), "Error".to_owned());
+		assert_eq!(result.error_count(), 1);
+	}
+
+	#[test]
+	fn unset_options() {
+		let a = Opt::new("-a", "--all", "All");
+		let b = Opt::new("-b", "--brief", "Brief");
+		let c = Opt::new("-c", "--count", "Count");
+		let parser = test_parser([&a, &b, &c]);
+		let result = parser.parse(args(["-b"]));
+		let unset : Vec<~str> = parser.unset_options(&result).iter().map(|opt| opt.long.clone()).collect();
+		assert_strs(unset.as_slice(), ["--all", "--count"]);
+	}
+
+	#[test]
+	fn required_one_of() {
+		let json = Opt::new("", "--json", "JSON output");
+		let xml = Opt::new("", "--xml", "XML output");
+		let mut parser = test_parser([&json, &xml]);
+		parser.add_required_one_of([&json, &xml]);
+		let result = parser.parse(args([]));
+		assert_eq!(first_error(&result), "One of the options --json, --xml is required".to_owned());
+		let result = parser.parse(args(["--xml"]));
+		assert!(result.errors.is_empty());
+		let result = parser.parse(args(["--json", "--xml"]));
+		assert_eq!(first_error(&result), "Only one of the options --json, --xml may be given (given --json, --xml)".to_owned());
+	}
+
+	#[test]
+	fn non_ascii_short_option() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["-é"]));
+		assert_eq!(status(&result), "Error".to_owned());
+		assert_eq!(first_error(&result), "Unknown option -é".to_owned());
+	}
+
+	#[test]
+	fn value_pattern() {
+		let mut id = Opt::new("", "--id ID", "Identifier");
+		id.pattern = Some("A-Za-z0-9_".to_owned());
+		let parser = test_parser([&id]);
+		let result = parser.parse(args(["--id", "abc_1"]));
+		assert_value(&parser, &result, &id, "abc_1");
+		let result = parser.parse(args(["--id", "x!y"]));
+		assert_eq!(first_error(&result), "Value 'x!y' for --id contains invalid characters".to_owned());
+	}
+
+	#[test]
+	fn path_checks() {
+		let dir = os::tmpdir();
+		let file = dir.join("optparse-test-path-checks.txt");
+		let missing = dir.join("optparse-test-path-checks-missing.txt");
+		File::create(&file).write_str("test\n").unwrap();
+		let dir_name = format!("{}", dir.display());
+		let file_name = format!("{}", file.display());
+		let missing_name = format!("{}", missing.display());
+
+		let mut input = Opt::new("", "--input FILE", "Input file");
+		input.check = Some(PathIsFile);
+		let mut config = Opt::new("", "--config FILE", "Config file");
+		config.check = Some(PathExists);
+		let mut output_dir = Opt::new("", "--dir DIR", "Output directory");
+		output_dir.check = Some(PathIsDir);
+		let parser = test_parser([&input, &config, &output_dir]);
+		let valid = parser.parse(args(["--input", file_name.as_slice(), "--config", dir_name.as_slice(),
+		  "--dir", dir_name.as_slice()]));
+		let not_found = parser.parse(args(["--config", missing_name.as_slice()]));
+		let not_file = parser.parse(args(["--input", dir_name.as_slice()]));
+		let not_dir = parser.parse(args(["--dir", file_name.as_slice()]));
+		let _ = fs::unlink(&file);
+
+		assert!(valid.errors.is_empty());
+		assert_eq!(first_error(&not_found), format!("--config: file '{}' does not exist", missing_name));
+		assert_eq!(first_error(&not_file), format!("--input: '{}' is not a file", dir_name));
+		assert_eq!(first_error(&not_dir), format!("--dir: '{}' is not a directory", file_name));
+	}
+
+	#[test]
+	fn extend_with_plugin_options() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let duplicate = Opt::new("-V", "--verbose", "Verbose output");
+		let host = Opt::new("-h", "--host HOST", "Host");
+		let mut parser = test_parser([&verbose]);
+		assert!(parser.extend(&test_parser([&duplicate])).is_err());
+		assert!(parser.extend(&test_parser([&host])).is_err());
+		assert!(parser.extend(&test_parser([&output])).is_ok());
+
+		let result = parser.parse(args(["-v", "-o", "x"]));
+		assert_set(&parser, &result, &verbose);
+		assert_value(&parser, &result, &output, "x");
+		assert!(parser.format_help_str().contains("--output FILE"));
+	}
+
+	#[test]
+	fn handlers_run_in_order() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let order = Cell::new(0u);
+		let mut verbose_calls = Vec::new();
+		let mut output_calls = Vec::new();
+		{
+			let mut parser = test_parser([&verbose, &output]);
+			parser.set_handler(&verbose, |val| {
+				verbose_calls.push((order.get(), val.map(|val| val.to_owned())));
+				order.set(order.get() + 1);
+			});
+			parser.set_handler(&output, |val| {
+				output_calls.push((order.get(), val.map(|val| val.to_owned())));
+				order.set(order.get() + 1);
+			});
+			let positionals = parser.run(args(["-o", "a", "-v", "file", "-o", "b"]));
+			assert!(positionals == Some(vec!("file".to_owned())));
+		}
+		assert!(verbose_calls == vec!((1, None)));
+		assert!(output_calls == vec!((0, Some("a".to_owned())), (2, Some("b".to_owned()))));
+	}
+
+	// suggestions and messages
+
+	#[test]
+	fn suggestions_for_mistyped_options() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["--verbsoe"]));
+		assert!(first_error(&result).starts_with("Unknown option --verbsoe, did you mean '--verbose'?"));
+		let result = parser.parse(args(["-verbsoe"]));
+		assert!(first_error(&result).starts_with("Unknown option -verbsoe, did you mean '--verbose'?"));
+		let result = parser.parse(args(["-x"]));
+		assert_eq!(first_error(&result), "Unknown option -x".to_owned());
+	}
+
+	#[test]
+	fn suggestions_ignore_extra_dashes() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let parser = test_parser([&verbose]);
+		let result = parser.parse(args(["---verbose"]));
+		assert!(first_error(&result).starts_with("Unknown option ---verbose, did you mean '--verbose'?"));
+		let result = parser.parse(args(["--verbose-"]));
+		assert!(first_error(&result).starts_with("Unknown option --verbose-, did you mean '--verbose'?"));
+	}
+
+	#[test]
+	fn suggestions_for_swapped_dashes() {
+		let file = Opt::new("-o", "--file FILE", "Output file");
+		let parser = test_parser([&file]);
+		let result = parser.parse(args(["--o"]));
+		assert!(first_error(&result).starts_with("Unknown option --o, did you mean '-o'?"));
+
+		let quiet = Opt::new("-q", "--quiet", "Quiet output");
+		let output = Opt::new("", "--output FILE", "Output file");
+		let parser = test_parser([&quiet, &output]);
+		let result = parser.parse(args(["-output"]));
+		assert!(first_error(&result).starts_with("Unknown option -output, did you mean '--output'?"));
+		assert_eq!(result.error_count(), 1);
+	}
+
+	#[test]
+	fn several_equally_close_suggestions() {
+		let abcd = Opt::new("", "--abcd", "Option abcd");
+		let abce = Opt::new("", "--abce", "Option abce");
+		let parser = test_parser([&abcd, &abce]);
+		let result = parser.parse(args(["--abcx"]));
+		assert_eq!(first_error(&result), "Unknown option --abcx, did you mean one of: --abcd, --abce?".to_owned());
+		let result = parser.parse(args(["--abdd"]));
+		assert!(first_error(&result).starts_with("Unknown option --abdd, did you mean '--abcd'?"));
+		let suggestions : Vec<~str> = parser.suggest_opts("--abdd", 2).iter().map(|opt| opt.long.clone()).collect();
+		assert_strs(suggestions.as_slice(), ["--abcd", "--abce"]);
+	}
+
+	#[test]
+	fn hidden_options_are_not_suggested() {
+		let mut secret = Opt::new("", "--secret", "Secret option");
+		secret.hidden = true;
+		let parser = test_parser([&secret]);
+		let result = parser.parse(args(["--secrte"]));
+		assert_eq!(first_error(&result), "Unknown option --secrte".to_owned());
+		let result = parser.parse(args(["secret"]));
+		assert!(result.warnings.is_empty());
+	}
+
+	#[test]
+	fn missing_dashes_note() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&verbose, &output]);
+		let result = parser.parse(args(["verbose"]));
+		assert_strs(result.warnings.as_slice(), ["note: did you mean the option --verbose?"]);
+		assert!(result.errors.is_empty());
+		assert_args(&result, ["verbose"]);
+
+		// an argument a single edit away from an option name
+		let result = parser.parse(args(["verbos"]));
+		assert_strs(result.warnings.as_slice(), ["note: did you mean the option --verbose?"]);
+		assert_args(&result, ["verbos"]);
+
+		// other words and the built-in options are not noted
+		let result = parser.parse(args(["help", "cut"]));
+		assert!(result.warnings.is_empty());
+	}
+
+	#[test]
+	fn custom_messages() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose]);
+		parser.messages.unknown_option = "Option inconnue {option}".to_owned();
+		parser.messages.usage = "Utilisation : {program} {usage}".to_owned();
+		let result = parser.parse(args(["--zzzzzz"]));
+		assert_eq!(first_error(&result), "Option inconnue --zzzzzz".to_owned());
+		assert_eq!(parser.usage_line(), "Utilisation : prog [options]".to_owned());
+	}
+
+	#[test]
+	fn error_report_includes_usage_if_enabled() {
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose]);
+		let result = parser.parse(args(["--verbose=1"]));
+		let error = first_error(&result);
+		assert_eq!(parser.error_report(&result), format!("{}\n\n", error));
+		parser.print_usage_on_error = true;
+		assert_eq!(parser.error_report(&result), format!("{}\n\nUsage: prog [options]\n", error));
+	}
+
+	// configuration checks
+
+	#[test]
+	fn validate_reports_conflicts() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let other = Opt::new("-o", "--other", "Another option");
+		let parser = test_parser([&output, &other]);
+		match parser.validate() {
+			Err(errors) => assert_strs(errors.as_slice(), ["Options --output and --other have the same short form -o"]),
+			Ok(()) => fail!("Expected the options to conflict")
+		}
+
+		let host = Opt::new("-h", "--host HOST", "Host");
+		let parser = test_parser([&host]);
+		match parser.validate() {
+			Err(errors) => assert_strs(errors.as_slice(), ["Options --host and --help have the same short form -h"]),
+			Ok(()) => fail!("Expected the options to conflict")
+		}
+	}
+
+	#[test]
+	fn validate_checks_defaults_and_references() {
+		let mut mode = Opt::new("", "--mode MODE", "Mode");
+		mode.choices = vec!("fast".to_owned(), "slow".to_owned());
+		mode.default = Some("fast".to_owned());
+		let json = Opt::new("", "--json", "JSON output");
+		let xml = Opt::new("", "--xml", "XML output");
+		let parser = test_parser([&mode, &json]);
+		assert!(parser.validate().is_ok());
+
+		let mut parser = test_parser([&mode, &json]);
+		parser.add_required_one_of([&json, &xml]);
+		assert!(parser.validate().is_err());
+
+		let mut parser = test_parser([&mode, &json]);
+		parser.set_trailing_target(&xml);
+		assert!(parser.validate().is_err());
+
+		let mut bad_mode = mode.clone();
+		bad_mode.default = Some("medium".to_owned());
+		let parser = test_parser([&bad_mode, &json]);
+		assert!(parser.validate().is_err());
+	}
+
+	#[test]
+	fn custom_help_option() {
+		let host = Opt::new("-h", "--host HOST", "Host");
+		let mut parser = test_parser([&host]);
+		parser.set_help_opt(Opt::new("", "--usage", "Show usage"));
+		let result = parser.parse(args(["--usage"]));
+		assert_eq!(status(&result), "Help".to_owned());
+		let result = parser.parse(args(["-h", "example.com"]));
+		assert_eq!(status(&result), "Success".to_owned());
+		assert_value(&parser, &result, &host, "example.com");
+	}
+
+	#[test]
+	fn version_option() {
+		let mut parser = test_parser([]);
+		parser.enable_version("1.2.3");
+		let result = parser.parse(args(["--version"]));
+		assert_eq!(status(&result), "Version".to_owned());
+		assert!(parser.wants_version(&result));
+		assert!(parser.version_line() == Some("prog 1.2.3".to_owned()));
+	}
+
+	// typed values
+
+	#[test]
+	fn typed_metavar_and_validation() {
+		let mut count = Opt::new("", "--count", "Count");
+		count.arg_type = Some(IntArg);
+		assert!(count.metavar() == Some("N".to_owned()));
+		let mut named = Opt::new("", "--count NUM", "Count");
+		named.arg_type = Some(IntArg);
+		assert!(named.metavar() == Some("NUM".to_owned()));
+
+		let parser = test_parser([&count]);
+		let result = parser.parse(args(["--count", "abc"]));
+		assert_eq!(first_error(&result), "Invalid value 'abc' for option --count, expected an integer".to_owned());
+		let result = parser.parse(args(["--count", "12"]));
+		assert_value(&parser, &result, &count, "12");
+	}
+
+	#[test]
+	fn float_values() {
+		let threshold = Opt::new("", "--threshold NUM", "Threshold");
+		let parser = test_parser([&threshold]);
+		let result = parser.parse(args(["--threshold", "0.75"]));
+		assert_eq!(ok_value(parser.float_value(&result, &threshold)), 0.75);
+		let result = parser.parse(args(["--threshold", "2"]));
+		assert_eq!(ok_value(parser.float_value(&result, &threshold)), 2.0);
+		let result = parser.parse(args(["--threshold", "abc"]));
+		assert!(is_err_value(parser.float_value(&result, &threshold)));
+	}
+
+	#[test]
+	fn size_and_duration_values() {
+		let size = Opt::new("", "--size SIZE", "Size");
+		let timeout = Opt::new("", "--timeout TIME", "Timeout");
+		let mut parser = test_parser([&size, &timeout]);
+		let result = parser.parse(args(["--size", "10M", "--timeout", "1.5h"]));
+		assert_eq!(ok_value(parser.size_value(&result, &size)), 10 * 1024 * 1024);
+		assert_eq!(ok_value(parser.duration_value(&result, &timeout)), 5400);
+		let result = parser.parse(args(["--size", "512", "--timeout", "30"]));
+		assert_eq!(ok_value(parser.size_value(&result, &size)), 512);
+		assert_eq!(ok_value(parser.duration_value(&result, &timeout)), 30);
+		let result = parser.parse(args(["--size", "10X", "--timeout", "abc"]));
+		assert!(is_err_value(parser.size_value(&result, &size)));
+		assert!(is_err_value(parser.duration_value(&result, &timeout)));
+
+		parser.decimal_sizes = true;
+		let result = parser.parse(args(["--size", "10M"]));
+		assert_eq!(ok_value(parser.size_value(&result, &size)), 10 * 1000 * 1000);
+	}
+
+	// help output
+
+	#[test]
+	fn help_does_not_end_with_newline() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&output]);
+		let help = parser.format_help_str();
+		assert_eq!(help, "Usage: prog [options]\n\nA test program\n\n  -o, --output FILE       Output file".to_owned());
+		assert!(!help.ends_with("\n"));
+	}
+
+	#[test]
+	fn banner_wraps_to_help_width() {
+		let mut parser = test_parser([]);
+		parser.usage = "".to_owned();
+		parser.banner = "aaa bbb ccc\n\nddd".to_owned();
+		parser.help_width = 8;
+		assert_eq!(parser.format_help_str(), "aaa bbb\nccc\n\nddd".to_owned());
+	}
+
+	#[test]
+	fn help_shows_defaults_if_enabled() {
+		let mut jobs = Opt::new("-j", "--jobs N", "Number of jobs");
+		jobs.default = Some("4".to_owned());
+		let mut parser = test_parser([&jobs]);
+		assert!(!parser.format_help_str().contains("(default: 4)"));
+		parser.show_defaults = true;
+		assert!(parser.format_help_str().contains("Number of jobs (default: 4)"));
+	}
+
+	#[test]
+	fn help_section_order() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let mut parser = test_parser([&output]);
+		parser.tail_banner = Some("Tail text".to_owned());
+		parser.epilog = Some("Epilog text".to_owned());
+		let help = parser.format_help_str();
+		let options_pos = help.find_str("Output file").unwrap();
+		let tail_pos = help.find_str("Tail text").unwrap();
+		let epilog_pos = help.find_str("Epilog text").unwrap();
+		assert!(options_pos < tail_pos && tail_pos < epilog_pos);
+	}
+
+	#[test]
+	fn program_name_override() {
+		let mut parser = test_parser([]);
+		parser.program_name = Some("tool".to_owned());
+		assert_eq!(parser.usage_line(), "Usage: tool [options]".to_owned());
+	}
+
+	#[test]
+	fn version_in_help() {
+		let mut parser = test_parser([]);
+		parser.version = Some("1.2.3".to_owned());
+		parser.show_version_in_help = true;
+		assert!(parser.format_help_str().starts_with("prog 1.2.3\n\nUsage: prog [options]"));
+	}
+
+	#[test]
+	fn compact_help_at_narrow_width() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let mut parser = test_parser([&output]);
+		parser.usage = "".to_owned();
+		parser.banner = "".to_owned();
+		parser.help_width = 30;
+		assert_eq!(parser.format_help_str(), "  -o, --output FILE\n        Output file".to_owned());
+	}
+
+	#[test]
+	fn help_placeholders() {
+		let mut parser = test_parser([]);
+		parser.version = Some("2.0".to_owned());
+		parser.usage = "[options] {unknown}".to_owned();
+		parser.banner = "{program} {version} copies files".to_owned();
+		assert_eq!(parser.usage_line(), "Usage: prog [options] {unknown}".to_owned());
+		assert!(parser.format_help_str().contains("prog 2.0 copies files"));
+	}
+
+	#[test]
+	fn long_names_are_aligned() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let long_only = Opt::new("", "--long", "Long only");
+		let parser = test_parser([&output, &long_only]);
+		assert!(parser.option_help(&output, 26, 80).starts_with("  -o, --output FILE"));
+		assert!(parser.option_help(&long_only, 26, 80).starts_with("      --long"));
+	}
+
+	#[test]
+	fn description_column() {
+		let short = Opt::new("-o", "--output", "Output file");
+		let equal = Opt::new("", "--abcdefghijkl", "Desc");
+		let longer = Opt::new("", "--abcdefghijklmnop", "Desc");
+		let parser = test_parser([&short, &equal, &longer]);
+		assert_eq!(parser.option_help(&short, 20, 80), "  -o, --output      Output file".to_owned());
+		assert_eq!(parser.option_help(&equal, 20, 80), format!("      --abcdefghijkl\n{}Desc", " ".repeat(20)));
+		assert_eq!(parser.option_help(&longer, 20, 80), format!("      --abcdefghijklmnop\n{}Desc", " ".repeat(20)));
+	}
+
+	#[test]
+	fn help_json_fields() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let color = Opt::new("", "--color [WHEN]", "Colorize output");
+		let parser = test_parser([&output, &color]);
+		let json = parser.help_json();
+		assert!(json.contains("{\"short\": \"-o\", \"long\": \"--output\", \"metavar\": \"FILE\", \
+		                       \"description\": \"Output file\", \"takes_arg\": true, \
+		                       \"required\": true"), "{}", json);
+		assert!(json.contains("{\"short\": null, \"long\": \"--color\", \"metavar\": \"[WHEN]\", \
+		                       \"description\": \"Colorize output\", \"takes_arg\": true, \
+		                       \"required\": false"), "{}", json);
+	}
+
+	#[test]
+	fn help_grouped_by_required() {
+		let mut output = Opt::new("-o", "--output FILE", "Output file");
+		output.required = true;
+		let verbose = Opt::new("-v", "--verbose", "Verbose output");
+		let mut parser = test_parser([&verbose, &output]);
+		parser.group_by_required = true;
+		let help = parser.format_help_str();
+		assert!(help.contains("Required options:\n  -o, --output FILE"), "{}", help);
+		assert!(help.contains("Optional options:\n  -v, --verbose"), "{}", help);
+		assert!(help.find_str("Required options:").unwrap() < help.find_str("Optional options:").unwrap());
+	}
+
+	#[test]
+	fn help_json_required_options() {
+		let mut color = Opt::new("", "--color [WHEN]", "Colorize output");
+		color.required = true;
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let parser = test_parser([&color, &output]);
+		let json = parser.help_json();
+		assert!(json.contains("\"long\": \"--color\", \"metavar\": \"[WHEN]\", \
+		                       \"description\": \"Colorize output\", \"takes_arg\": true, \
+		                       \"required\": false, \"option_required\": true}"), "{}", json);
+		assert!(json.contains("\"required\": true, \"option_required\": false}"), "{}", json);
+	}
+
+	#[test]
+	fn options_summary_skips_hidden_options() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let mut secret = Opt::new("", "--secret", "Secret option");
+		secret.hidden = true;
+		let parser = test_parser([&output, &secret]);
+		assert_eq!(parser.options_summary(), "Options: -o --output -h --help".to_owned());
+	}
+
+	#[test]
+	fn empty_banner_is_left_out() {
+		let output = Opt::new("-o", "--output FILE", "Output file");
+		let mut parser = test_parser([&output]);
+		parser.banner = "".to_owned();
+		let help = parser.format_help_str();
+		assert!(help.starts_with("Usage: prog [options]\n\n  -o, --output FILE"), "{}", help);
+		assert!(!help.contains("\n\n\n"));
 	}
 }
@@ -0,0 +1,51 @@
+//! Helpers for checking the outcome of parsing a command line
+//! in tests, which fail with a message describing the result
+//! when it is not what was expected.
+
+use optparse::{Opt, OptionParser, ParseResult};
+
+/// Fails unless @p opt was set in @p result
+pub fn assert_set(parser: &OptionParser, result: &ParseResult, opt: &Opt) {
+	if !parser.is_set(result, opt) {
+		fail!("Expected option {} to be set in {}", opt_name(opt), *result);
+	}
+}
+
+/// Fails unless @p opt was not set in @p result
+pub fn assert_not_set(parser: &OptionParser, result: &ParseResult, opt: &Opt) {
+	if parser.is_set(result, opt) {
+		fail!("Expected option {} not to be set in {}", opt_name(opt), *result);
+	}
+}
+
+/// Fails unless the value of @p opt in @p result is @p expected
+pub fn assert_value(parser: &OptionParser, result: &ParseResult, opt: &Opt, expected: &str) {
+	match parser.value(result, opt) {
+		Some(val) if val == expected => (),
+		Some(val) => fail!("Expected option {} to have value '{}' but it was '{}' in {}",
+		                   opt_name(opt), expected, val, *result),
+		None => fail!("Expected option {} to have value '{}' but it has no value in {}",
+		              opt_name(opt), expected, *result)
+	}
+}
+
+/// Fails unless the non-option arguments in @p result are @p expected
+pub fn assert_args(result: &ParseResult, expected: &[&str]) {
+	let args : Vec<&str> = result.args.iter().map(|arg| arg.as_slice()).collect();
+	if args.as_slice() != expected {
+		fail!("Expected arguments [{}] but got [{}]", expected.connect(", "), args.connect(", "));
+	}
+}
+
+/// Fails unless parsing @p result produced errors
+pub fn assert_error(result: &ParseResult) {
+	if result.errors.is_empty() {
+		fail!("Expected errors but parsing succeeded with {}", *result);
+	}
+}
+
+// returns the long name of an option without its argument,
+// eg. '--output' for '--output <file>'
+fn opt_name<'r>(opt: &'r Opt) -> &'r str {
+	opt.long.split(' ').next().unwrap()
+}